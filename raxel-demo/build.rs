@@ -1,12 +1,16 @@
-use std::{env, path::PathBuf};
+use std::{env, fs, path::PathBuf};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir = env::var("PROFILE").unwrap();
     let out_dir = PathBuf::from(format!("../target/{}", out_dir));
-    fs_extra::dir::copy(
-        "./src/resources",
-        &out_dir,
-        &fs_extra::dir::CopyOptions::new().overwrite(true),
-    )?;
+    // Examples are built into their own folder and look for the resources next to them.
+    for out_dir in [out_dir.clone(), out_dir.join("examples")] {
+        fs::create_dir_all(&out_dir)?;
+        fs_extra::dir::copy(
+            "./src/resources",
+            &out_dir,
+            &fs_extra::dir::CopyOptions::new().overwrite(true),
+        )?;
+    }
     Ok(())
 }
@@ -0,0 +1,74 @@
+//! Renders a small world without a window and writes it to `headless.png`, or to the path passed as the
+//! first argument. `--fallback` renders with a software adapter instead of the GPU.
+use std::{env, process};
+
+use raxel::{
+    headless_renderer, register_voxels,
+    resource::{resource::LoadedResource, texture_system::add_texture},
+    voxel::{
+        voxel::{Transparency, Voxel},
+        voxelmodel::VoxelModel,
+    },
+    world::world::World,
+};
+
+static mut STONE: usize = 0;
+static mut DIRT: usize = 0;
+
+fn solid_texture(color: [u8; 4]) -> LoadedResource<'static> {
+    LoadedResource::TEXTURE(image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(16, 16, image::Rgba(color))))
+}
+
+fn main() {
+    let force_fallback_adapter = env::args().any(|arg| arg == "--fallback");
+    let path = env::args().skip(1).find(|arg| !arg.starts_with("--")).unwrap_or("headless.png".to_string());
+
+    register_voxels(&|voxel_registry| unsafe {
+        let stone = add_texture(solid_texture([120, 120, 120, 255])).unwrap();
+        let dirt = add_texture(solid_texture([110, 80, 50, 255])).unwrap();
+        voxel_registry.register("air", Voxel { model: None, emission: 0, transparency: Transparency::OPAQUE });
+        STONE = voxel_registry.register("stone", Voxel { model: Some(VoxelModel::all(stone)), emission: 0, transparency: Transparency::OPAQUE });
+        DIRT = voxel_registry.register("dirt", Voxel { model: Some(VoxelModel::all(dirt)), emission: 0, transparency: Transparency::OPAQUE });
+    });
+    let mut renderer = headless_renderer(320, 240, force_fallback_adapter).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    // Flat stone below y = 0 with a dirt pillar in front of the camera.
+    let mut world = World::new(1, Box::new(|cell| unsafe {
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    if cell.pos.y < 0 {
+                        cell.set(x, y, z, STONE);
+                    } else if cell.pos.y == 0 && (x, z) == (24, 16) && y < 6 {
+                        cell.set(x, y, z, DIRT);
+                    }
+                }
+            }
+        }
+    }));
+    renderer.camera.pos.x = 8.0;
+    renderer.camera.pos.y = 8.0;
+    renderer.camera.pos.z = 16.0;
+    renderer.camera.pitch = -0.4;
+    while world.cells.len() < 27 {
+        world.stream(renderer.camera.pos);
+    }
+    renderer.remesh_dirty_cells(&mut world);
+
+    let image = renderer.render_to_image().unwrap_or_else(|err| {
+        eprintln!("Failed to read the frame back: {err}");
+        process::exit(1);
+    });
+    if image.pixels().all(|pixel| pixel == image.get_pixel(0, 0)) {
+        eprintln!("The frame is a single colour, the world wasn't drawn!");
+        process::exit(1);
+    }
+    image.save(&path).unwrap_or_else(|err| {
+        eprintln!("Failed to write {path}: {err}");
+        process::exit(1);
+    });
+    println!("Wrote {path}");
+}
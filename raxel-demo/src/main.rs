@@ -1,10 +1,13 @@
 use raxel::{
     resource::{
         resource::{ResourceId, ResourceLoader},
-        texture_system::add_texture,
+        texture_system::add_named_texture,
     },
-    voxel::{voxel::Voxel, voxelmodel::VoxelModel},
-    world::world::{World, WORLD_LENGTH},
+    voxel::{
+        modelfile::load_model,
+        voxel::{Transparency, Voxel},
+    },
+    world::world::World,
 };
 use simdnoise::NoiseBuilder;
 
@@ -12,55 +15,126 @@ static mut AIR: usize = 0;
 static mut DIRT: usize = 0;
 static mut STONE: usize = 0;
 static mut GRASS: usize = 0;
+static mut LAMP: usize = 0;
+static mut GLASS: usize = 0;
+static mut STONE_SLAB: usize = 0;
+static mut STONE_STAIRS: usize = 0;
+
+/// The range of the gradient noise at a frequency of 0.01, from about -0.021 to 0.021 over the 256 by 256
+/// voxels of the old fixed-size world. Dividing by it maps the noise to 0 to 1, like `generate_scaled(0.0, 1.0)`
+/// did with the bounds of that world.
+const NOISE_RANGE: f32 = 0.042;
+
+/// The voxel standing on the surface of the world column at `x`, `z`, if any. A hash of the column
+/// scatters a few glass blocks, slabs and stairs over the terrain.
+unsafe fn decoration(x: i32, z: i32) -> Option<usize> {
+    match (x.wrapping_mul(73856093) ^ z.wrapping_mul(19349663)).rem_euclid(256) {
+        0 => Some(GLASS),
+        1 => Some(STONE_SLAB),
+        2 => Some(STONE_STAIRS),
+        _ => None,
+    }
+}
 
 pub fn main() {
     let mut instance = raxel::Instance::new(None, &|voxel_registry| unsafe {
-        let _ = add_texture(
+        let _ = add_named_texture(
+            "dirt",
             ResourceId(Some("texture".to_string()), "dirt.png".to_string())
                 .load(ResourceLoader::TEXTURE),
         );
-        let _ = add_texture(
+        let _ = add_named_texture(
+            "stone",
             ResourceId(Some("texture".to_string()), "stone.png".to_string())
                 .load(ResourceLoader::TEXTURE),
         );
-        let _ = add_texture(
+        let _ = add_named_texture(
+            "grass_top",
             ResourceId(Some("texture".to_string()), "grass_top.png".to_string())
                 .load(ResourceLoader::TEXTURE),
         );
-        let _ = add_texture(
+        let _ = add_named_texture(
+            "grass_side",
             ResourceId(Some("texture".to_string()), "grass_side.png".to_string())
                 .load(ResourceLoader::TEXTURE),
         );
+        let _ = add_named_texture(
+            "glass",
+            ResourceId(Some("texture".to_string()), "glass.png".to_string())
+                .load(ResourceLoader::TEXTURE),
+        );
+
+        let model = |name: &str| {
+            load_model(&ResourceId(Some("model".to_string()), format!("{name}.json")))
+                .unwrap_or_else(|err| panic!("{err}"))
+        };
 
-        AIR = voxel_registry.register(Voxel { model: None });
-        DIRT = voxel_registry.register(Voxel {
-            model: Some(VoxelModel::all(0)),
+        AIR = voxel_registry.register("air", Voxel {
+            model: None,
+            emission: 0,
+            transparency: Transparency::OPAQUE,
         });
-        STONE = voxel_registry.register(Voxel {
-            model: Some(VoxelModel::all(1)),
+        DIRT = voxel_registry.register("dirt", Voxel {
+            model: Some(model("dirt")),
+            emission: 0,
+            transparency: Transparency::OPAQUE,
         });
-        GRASS = voxel_registry.register(Voxel {
-            model: Some(VoxelModel::top_bottom(0, 2, 3)),
+        STONE = voxel_registry.register("stone", Voxel {
+            model: Some(model("stone")),
+            emission: 0,
+            transparency: Transparency::OPAQUE,
+        });
+        GRASS = voxel_registry.register("grass", Voxel {
+            model: Some(model("grass")),
+            emission: 0,
+            transparency: Transparency::OPAQUE,
+        });
+        LAMP = voxel_registry.register("lamp", Voxel {
+            model: Some(model("stone")),
+            emission: 15,
+            transparency: Transparency::OPAQUE,
+        });
+        GLASS = voxel_registry.register("glass", Voxel {
+            model: Some(model("glass")),
+            emission: 0,
+            transparency: Transparency::TRANSLUCENT,
+        });
+        STONE_SLAB = voxel_registry.register("stone_slab", Voxel {
+            model: Some(model("stone_slab")),
+            emission: 0,
+            transparency: Transparency::OPAQUE,
+        });
+        STONE_STAIRS = voxel_registry.register("stone_stairs", Voxel {
+            model: Some(model("stone_stairs")),
+            emission: 0,
+            transparency: Transparency::OPAQUE,
         });
     });
-    let noise = NoiseBuilder::gradient_2d(32 * WORLD_LENGTH as usize, 32 * WORLD_LENGTH as usize)
+    let world = World::new(3, Box::new(|cell| unsafe {
+        // The noise is scaled by fixed bounds instead of the bounds of each cell's noise, so cells line up.
+        let (noise, _, _) = NoiseBuilder::gradient_2d_offset(
+            (cell.pos.z * 32) as f32,
+            32,
+            (cell.pos.x * 32) as f32,
+            32,
+        )
         .with_freq(0.01)
-        .generate_scaled(0.0, 1.0);
-    let world = World::new(&mut instance.renderer, &|cell| unsafe {
+        .generate();
         for x in 0..32 {
             for z in 0..32 {
-                let height = ((((*noise.get_unchecked(
-                    ((x + cell.pos.x * 32) << 8 | (z + cell.pos.z * 32) << 0) as usize,
-                ) + 1.0)
-                    / 230.0)
-                    * 5000.0)
-                    .round() as u32
-                    + 30
-                ).min(224);
+                let noise = (*noise.get_unchecked((x << 5 | z) as usize) / NOISE_RANGE + 0.5).clamp(0.0, 1.0);
+                // The clamped noise keeps the surface between y = 52 and 73. The old world was 256 voxels
+                // high and capped the height at 224 to stay inside it, streamed worlds have no top.
+                let height = (((noise + 1.0) / 230.0) * 5000.0).round() as i32 + 30;
 
+                let decoration = decoration(x + cell.pos.x * 32, z + cell.pos.z * 32);
                 for y in 0..32 {
                     let world_y = y + cell.pos.y * 32;
-                    if world_y > height {
+                    if world_y == height + 1 {
+                        if let Some(id) = decoration {
+                            cell.set(x as u8, y as u8, z as u8, id);
+                        }
+                    } else if world_y > height {
                         continue;
                     } else if world_y == height {
                         cell.set(x as u8, y as u8, z as u8, GRASS);
@@ -72,7 +146,9 @@ pub fn main() {
                 }
             }
         }
-    });
+    }));
     instance.set_world(world);
+    instance.set_place_voxel(unsafe { LAMP });
+    instance.set_break_voxel(unsafe { AIR });
     instance.run();
 }
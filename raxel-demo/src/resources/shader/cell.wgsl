@@ -9,13 +9,38 @@ var<uniform> camera_uniforms: CameraUniforms;
 
 @group(0)
 @binding(0)
-var<storage, read> faces: array<u32>;
+var<storage, read> faces: array<vec2<u32>>;
+
+@group(3)
+@binding(0)
+var<storage, read> cell_positions: array<vec4<i32>>;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coord: vec2<f32>,
     @location(1) tex_id: i32,
     @location(2) light: f32,
+    @location(3) @interpolate(flat) face_normal: u32,
+    @location(4) @interpolate(flat) cell_index: u32,
+    @location(5) @interpolate(flat) face_size: vec2<f32>,
+    @location(6) @interpolate(flat) cutout: u32,
+}
+
+// The light of a face from its normal and the sky and block light packed into `shading`.
+fn face_light(face_normal: u32, shading: u32) -> f32 {
+    var normal_light_lookup: array<f32, 7> = array<f32, 7>(
+        0.6,
+        0.8,
+        0.4,
+        0.6,
+        0.8,
+        1.0,
+        0.8, // Diagonal
+    );
+    // Each light level below the brighter of the sky and block light in front of the face dims it a bit more.
+    let sky_light = shading & 15u;
+    let block_light = (shading >> 4u) & 15u;
+    return normal_light_lookup[face_normal] * pow(0.8, f32(15u - max(sky_light, block_light)));
 }
 
 @vertex
@@ -32,40 +57,86 @@ fn vert(@builtin(instance_index) instance_index: u32, @builtin(vertex_index) ver
         vec2<f32>(0.0, 1.0), vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0),
         vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
     );
-    var normal_light_lookup: array<f32, 6> = array<f32, 6>(
-        0.6,
-        0.8,
-        0.4,
-        0.6,
-        0.8,
-        1.0,
-    );
 
-                //     mesh.push(
-                //     (((idx >> 10 & 31) as u32) << 0)
-                //         | (((idx >> 5 & 31) as u32) << 5)
-                //         | (((idx >> 0 & 31) as u32) << 10)
-                //         | ((face.norm as u32) << 15)
-                //         | ((face.tex_id as u32) << 18)
-                //         | (31 << 25)
-                //         | (31 << 30)
-                // );
+    let packed_face = faces[vertex_index / 6u];
+    let face = packed_face.x;
 
-    let face = faces[vertex_index / 6u];
-    let corner_index = vertex_index % 6u;
+    // The occlusion of the face's corners from 0 (enclosed) to 3 (open). The quad is split along the diagonal
+    // between the brighter pair of opposite corners, so the occlusion is interpolated the same way on every face.
+    let occlusion = packed_face.y >> 8u;
+    var corner_occlusion: array<u32, 4> = array<u32, 4>(occlusion & 3u, (occlusion >> 2u) & 3u, (occlusion >> 4u) & 3u, (occlusion >> 6u) & 3u);
+    var occlusion_light_lookup: array<f32, 4> = array<f32, 4>(0.4, 0.6, 0.8, 1.0);
+    var triangle_corners: array<array<u32, 6>, 2> = array<array<u32, 6>, 2>(
+        array<u32, 6>(0u, 1u, 2u, 2u, 3u, 0u),
+        array<u32, 6>(1u, 2u, 3u, 3u, 0u, 1u),
+    );
+    // The entry of each corner in `vertex_pos_lookup` and `texture_coords`.
+    var corner_vertices: array<u32, 4> = array<u32, 4>(0u, 1u, 2u, 4u);
+    let flipped = u32(corner_occlusion[1] + corner_occlusion[3] > corner_occlusion[0] + corner_occlusion[2]);
+    let corner = triangle_corners[flipped][vertex_index % 6u];
+    let corner_index = corner_vertices[corner];
 
     let face_normal = (face >> 15u) & 7u;
     let face_width = f32((face >> 21u) & 31u) + 1.0;
     let face_height = f32((face >> 26u) & 31u) + 1.0;
 
+    // Greedy meshed faces span `face_width` voxels along the texture's u axis and `face_height` along its v axis.
+    var face_scale_lookup: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+        vec3<f32>(face_width, face_height, 1.0), // North
+        vec3<f32>(1.0, face_height, face_width), // West
+        vec3<f32>(face_height, 1.0, face_width), // Down
+        vec3<f32>(face_width, face_height, 1.0), // South
+        vec3<f32>(1.0, face_height, face_width), // East
+        vec3<f32>(face_width, 1.0, face_height), // Up
+    );
+
     var transformed_pos = vec3<f32>(f32((face >> 10u) & 31u), f32((face >> 5u) & 31u), f32((face >> 0u) & 31u));
-    transformed_pos += vertex_pos_lookup[face_normal][corner_index] * vec3<f32>(face_width, face_width, face_height);
+    transformed_pos += vertex_pos_lookup[face_normal][corner_index] * face_scale_lookup[face_normal];
 
     var out: VertexOutput;
-    out.position = (camera_uniforms.projection * camera_uniforms.view) * vec4<f32>(transformed_pos + vec3<f32>(f32((instance_index >> 6u) & 7u), f32((instance_index >> 3u) & 7u), f32((instance_index >> 0u) & 7u)) * 32.0, 1.0);
+    out.position = (camera_uniforms.projection * camera_uniforms.view) * vec4<f32>(transformed_pos + vec3<f32>(cell_positions[instance_index].xyz) * CELL_LENGTH, 1.0);
     out.tex_coord = texture_coords[corner_index] * vec2<f32>(face_width, face_height);
-    out.tex_id = i32((face >> 18u) & 7u);
-    out.light = normal_light_lookup[face_normal];
+    out.tex_id = i32(packed_face.y >> 16u);
+    out.light = face_light(face_normal, packed_face.y) * occlusion_light_lookup[corner_occlusion[corner]];
+    out.face_normal = face_normal;
+    out.cell_index = instance_index;
+    out.face_size = vec2<f32>(face_width, face_height);
+    out.cutout = face >> 31u;
+    return out;
+}
+
+// Draws the quads of non-cube voxels, see `ShapeQuad`. Each quad takes up three entries of `faces`.
+@vertex
+fn vert_shape(@builtin(instance_index) instance_index: u32, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let quad_index = vertex_index / 6u * 3u;
+    let corners = vec4<u32>(faces[quad_index].xy, faces[quad_index + 1u].xy);
+    let tex_rect = faces[quad_index + 2u].x;
+    let shading = faces[quad_index + 2u].y;
+
+    var quad_corners: array<u32, 6> = array<u32, 6>(0u, 1u, 2u, 2u, 3u, 0u);
+    let corner = quad_corners[vertex_index % 6u];
+    let packed_corner = corners[corner];
+    let corner_pos = vec3<f32>(f32((packed_corner >> 20u) & 1023u), f32((packed_corner >> 10u) & 1023u), f32(packed_corner & 1023u)) / 16.0;
+
+    let u0 = f32(tex_rect & 31u);
+    let v0 = f32((tex_rect >> 5u) & 31u);
+    let u1 = f32((tex_rect >> 10u) & 31u);
+    let v1 = f32((tex_rect >> 15u) & 31u);
+    var texture_coords: array<vec2<f32>, 4> = array<vec2<f32>, 4>(
+        vec2<f32>(u0, v1), vec2<f32>(u0, v0), vec2<f32>(u1, v0), vec2<f32>(u1, v1),
+    );
+
+    let face_normal = (tex_rect >> 20u) & 7u;
+
+    var out: VertexOutput;
+    out.position = (camera_uniforms.projection * camera_uniforms.view) * vec4<f32>(corner_pos + vec3<f32>(cell_positions[instance_index].xyz) * CELL_LENGTH, 1.0);
+    out.tex_coord = texture_coords[corner] / 16.0;
+    out.tex_id = i32(shading >> 16u);
+    out.light = face_light(face_normal, shading);
+    out.face_normal = face_normal;
+    out.cell_index = instance_index;
+    out.face_size = vec2<f32>(1.0, 1.0);
+    out.cutout = tex_rect >> 31u;
     return out;
 }
 
@@ -78,5 +149,48 @@ var block_tex_sampler: sampler;
 
 @fragment
 fn frag(vertex: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(block_tex_array, block_tex_sampler, vertex.tex_coord, vertex.tex_id) * vertex.light;
+    let color = textureSample(block_tex_array, block_tex_sampler, vertex.tex_coord, vertex.tex_id);
+    // Cutout faces leave out their transparent texels, translucent faces are blended by their pipeline.
+    if (vertex.cutout == 1u && color.a < 0.5) {
+        discard;
+    }
+    return vec4<f32>(color.rgb * vertex.light, color.a);
+}
+
+// The debug render modes, see `RenderMode`.
+
+@fragment
+fn frag_wireframe(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+}
+
+@fragment
+fn frag_normal(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    var normal_color_lookup: array<vec3<f32>, 7> = array<vec3<f32>, 7>(
+        vec3<f32>(0.0, 0.0, 0.5), // North
+        vec3<f32>(0.5, 0.0, 0.0), // West
+        vec3<f32>(0.0, 0.5, 0.0), // Down
+        vec3<f32>(0.0, 0.0, 1.0), // South
+        vec3<f32>(1.0, 0.0, 0.0), // East
+        vec3<f32>(0.0, 1.0, 0.0), // Up
+        vec3<f32>(1.0, 1.0, 0.0), // Diagonal
+    );
+    return vec4<f32>(normal_color_lookup[vertex.face_normal], 1.0);
+}
+
+@fragment
+fn frag_cell(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    // Hashes the cell's slot into a colour, so neighbouring cells are unlikely to look alike.
+    var hash = vertex.cell_index * 747796405u + 2891336453u;
+    hash = ((hash >> ((hash >> 28u) + 4u)) ^ hash) * 277803737u;
+    hash = (hash >> 22u) ^ hash;
+    let color = vec3<f32>(f32(hash & 255u), f32((hash >> 8u) & 255u), f32((hash >> 16u) & 255u)) / 255.0;
+    return vec4<f32>(color * vertex.light, 1.0);
+}
+
+@fragment
+fn frag_quad_size(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    // A quad covers at most 32 * 32 voxels, so the base two logarithm of its area lies between 0 and 10.
+    let size = log2(vertex.face_size.x * vertex.face_size.y) / 10.0;
+    return vec4<f32>(mix(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), size) * vertex.light, 1.0);
 }
@@ -9,9 +9,32 @@ struct DrawIndirect {
     base_instance: u32,
 }
 
+struct DrawCounts {
+    /// The number of opaque draw commands written to the start of `indirect_buffer`.
+    count: atomic<u32>,
+    /// The number of opaque and translucent faces drawn.
+    face_count: atomic<u32>,
+    /// The number of shape draw commands written to `indirect_buffer` from `MAX_LOADED_CELLS` on.
+    shape_count: atomic<u32>,
+    /// The number of translucent draw commands written to `indirect_buffer` from `2 * MAX_LOADED_CELLS` on,
+    /// up to the last visible cell in their back to front order.
+    translucent_count: atomic<u32>,
+    /// The number of cells inside the view frustum with anything to draw.
+    visible_cells: atomic<u32>,
+    /// The number of shape quads drawn.
+    shape_quad_count: atomic<u32>,
+}
+
+/// The vertices of the opaque faces, the shape quads and the translucent faces of a slot.
+struct SlotVertexCounts {
+    opaque: u32,
+    shapes: u32,
+    translucent: u32,
+}
+
 @group(0)
 @binding(0)
-var<storage, read_write> count: atomic<u32>;
+var<storage, read_write> draw_counts: DrawCounts;
 
 @group(0)
 @binding(1)
@@ -19,18 +42,99 @@ var<storage, read_write> indirect_buffer: array<DrawIndirect>;
 
 @group(0)
 @binding(2)
-var<storage, read> vertex_count_buffer: array<u32>;
+var<storage, read> vertex_count_buffer: array<SlotVertexCounts>;
 
-@compute
-@workgroup_size(8, 8, 8)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let idx = (global_id.x << 6u) | (global_id.y << 3u) | (global_id.z << 0u);
+struct CameraUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+@group(0)
+@binding(3)
+var<uniform> camera_uniforms: CameraUniforms;
+
+@group(0)
+@binding(4)
+var<storage, read> cell_positions: array<vec4<i32>>;
+
+/// The place of each slot's translucent draw in the back to front order of the cells.
+@group(0)
+@binding(5)
+var<storage, read> translucent_order: array<u32>;
 
-    atomicAdd(&count, 1u);
+// Tests the box against the frustum planes extracted from the view-projection matrix (Gribb & Hartmann).
+fn intersects_frustum(view_projection: mat4x4<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> bool {
+    let row_x = vec4<f32>(view_projection[0].x, view_projection[1].x, view_projection[2].x, view_projection[3].x);
+    let row_y = vec4<f32>(view_projection[0].y, view_projection[1].y, view_projection[2].y, view_projection[3].y);
+    let row_z = vec4<f32>(view_projection[0].z, view_projection[1].z, view_projection[2].z, view_projection[3].z);
+    let row_w = vec4<f32>(view_projection[0].w, view_projection[1].w, view_projection[2].w, view_projection[3].w);
+    var planes: array<vec4<f32>, 6> = array<vec4<f32>, 6>(
+        row_w + row_x,
+        row_w - row_x,
+        row_w + row_y,
+        row_w - row_y,
+        row_w + row_z,
+        row_w - row_z,
+    );
+
+    for (var i = 0; i < 6; i++) {
+        let plane = planes[i];
+        // The corner of the box furthest along the plane normal.
+        let corner = select(box_min, box_max, plane.xyz >= vec3<f32>(0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn draw_command(vertex_count: u32, base_vertex: u32, idx: u32) -> DrawIndirect {
     var command: DrawIndirect;
-    command.vertex_count = vertex_count_buffer[idx];
+    command.vertex_count = vertex_count;
     command.instance_count = 1u;
-    command.base_vertex = (1179648u * idx);
+    command.base_vertex = base_vertex;
     command.base_instance = idx;
-    indirect_buffer[idx] = command;
+    return command;
+}
+
+@compute
+@workgroup_size(64)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    if (idx >= arrayLength(&vertex_count_buffer)) {
+        return;
+    }
+
+    let vertex_counts = vertex_count_buffer[idx];
+    if (vertex_counts.opaque == 0u && vertex_counts.shapes == 0u && vertex_counts.translucent == 0u) {
+        return;
+    }
+
+    let box_min = vec3<f32>(cell_positions[idx].xyz) * CELL_LENGTH;
+    let visible = intersects_frustum(camera_uniforms.projection * camera_uniforms.view, box_min, box_min + vec3<f32>(CELL_LENGTH));
+
+    // Translucent draws keep the order of the cells, hidden cells leave an empty draw in their place.
+    let order = translucent_order[idx];
+    if (vertex_counts.translucent > 0u && order < MAX_LOADED_CELLS) {
+        var command = draw_command(vertex_counts.translucent, TRANSLUCENT_VERTEX_STRIDE * idx, idx);
+        command.instance_count = u32(visible);
+        indirect_buffer[2u * MAX_LOADED_CELLS + order] = command;
+        if (visible) {
+            atomicMax(&draw_counts.translucent_count, order + 1u);
+            atomicAdd(&draw_counts.face_count, vertex_counts.translucent / 6u);
+        }
+    }
+    if (!visible) {
+        return;
+    }
+    atomicAdd(&draw_counts.visible_cells, 1u);
+
+    if (vertex_counts.opaque > 0u) {
+        indirect_buffer[atomicAdd(&draw_counts.count, 1u)] = draw_command(vertex_counts.opaque, CELL_VERTEX_STRIDE * idx, idx);
+        atomicAdd(&draw_counts.face_count, vertex_counts.opaque / 6u);
+    }
+    if (vertex_counts.shapes > 0u) {
+        indirect_buffer[MAX_LOADED_CELLS + atomicAdd(&draw_counts.shape_count, 1u)] = draw_command(vertex_counts.shapes, SHAPE_VERTEX_STRIDE * idx, idx);
+        atomicAdd(&draw_counts.shape_quad_count, vertex_counts.shapes / 6u);
+    }
 }
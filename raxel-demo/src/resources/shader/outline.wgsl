@@ -0,0 +1,43 @@
+struct CameraUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera_uniforms: CameraUniforms;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vert(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) box_min: vec3<f32>,
+    @location(1) box_size: vec3<f32>,
+    @location(2) color: vec4<f32>,
+) -> VertexOutput {
+    // The 12 edges of a unit cube as pairs of corners, the corner index holds x, y and z in bits 0, 1 and 2.
+    var edge_corners: array<u32, 24> = array<u32, 24>(
+        0u, 1u, 2u, 3u, 4u, 5u, 6u, 7u, // Along x
+        0u, 2u, 1u, 3u, 4u, 6u, 5u, 7u, // Along y
+        0u, 4u, 1u, 5u, 2u, 6u, 3u, 7u, // Along z
+    );
+    let corner = edge_corners[vertex_index];
+    let corner_pos = vec3<f32>(f32(corner & 1u), f32((corner >> 1u) & 1u), f32((corner >> 2u) & 1u));
+
+    var out: VertexOutput;
+    out.position = (camera_uniforms.projection * camera_uniforms.view) * vec4<f32>(box_min + corner_pos * box_size, 1.0);
+    // Depth bias states don't apply to lines, so the lines are pulled towards the camera here to win
+    // against the faces they lie on.
+    out.position.z -= 0.001 * out.position.w;
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn frag(vertex: VertexOutput) -> @location(0) vec4<f32> {
+    return vertex.color;
+}
@@ -1,7 +1,8 @@
 #![feature(new_uninit)]
 #![feature(portable_simd)]
 #![feature(array_chunks)]
-mod render;
+pub mod mesh;
+pub mod render;
 pub mod resource;
 pub mod voxel;
 pub mod world;
@@ -9,10 +10,13 @@ pub mod world;
 use std::time::Instant;
 
 use futures::executor::block_on;
-use render::renderer::{Renderer, RendererState};
-use voxel::voxel::VoxelRegistry;
+use render::{
+    outline::OutlineBox,
+    renderer::{Renderer, RendererState, FACE_BUFFER_SIZE},
+};
+use voxel::{voxel::VoxelRegistry, voxelface::Norm};
 use winit::{
-    event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{CursorGrabMode, Window, WindowBuilder},
 };
@@ -20,12 +24,23 @@ use world::world::World;
 
 static mut VOXEL_REGISTRY: VoxelRegistry = VoxelRegistry::new();
 
+/// How far away voxels can be broken and placed.
+const REACH: f32 = 64.0;
+const TARGET_OUTLINE_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+/// The colours of the cell border overlay for cells without faces, with faces and waiting to be remeshed.
+const EMPTY_CELL_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 0.4];
+const MESHED_CELL_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 0.6];
+const DIRTY_CELL_COLOR: [f32; 4] = [1.0, 0.2, 0.0, 0.8];
+
 pub struct Instance {
     event_loop: EventLoop<()>,
     window: Window,
+    window_title: String,
     pub renderer: Renderer,
 
     world: Option<World>,
+    place_voxel: Option<usize>,
+    break_voxel: usize,
 }
 
 impl Instance {
@@ -34,12 +49,13 @@ impl Instance {
         register_voxels: &dyn Fn(&'static mut VoxelRegistry),
     ) -> Self {
         let event_loop = EventLoop::new();
+        let window_title = window_title.unwrap_or("Raxel").to_string();
         let window = WindowBuilder::new()
-            .with_title(window_title.unwrap_or("Raxel"))
+            .with_title(&window_title)
             .build(&event_loop)
             .unwrap();
 
-        unsafe { register_voxels(&mut VOXEL_REGISTRY) };
+        self::register_voxels(register_voxels);
 
         let renderer = block_on(init_renderer(&window));
 
@@ -47,20 +63,73 @@ impl Instance {
             renderer,
             event_loop,
             window,
+            window_title,
 
             world: None,
+            place_voxel: None,
+            // Cells start out filled with the voxel of id 0, which is the empty voxel unless registered otherwise.
+            break_voxel: 0,
         }
     }
 
-    pub fn set_world(&mut self, world: World) {
+    /// Sets the voxel placed by right-clicking, nothing is placed until one is set.
+    pub fn set_place_voxel(&mut self, id: usize) {
+        self.place_voxel = Some(id);
+    }
+
+    /// Sets the voxel left-clicked voxels are replaced with, the voxel of id 0 until one is set.
+    pub fn set_break_voxel(&mut self, id: usize) {
+        self.break_voxel = id;
+    }
+
+    /// Replaces the current world, meshing and uploading all of the new world's cells.
+    pub fn set_world(&mut self, mut world: World) {
+        if let Some(old_world) = self.world.take() {
+            for pos in old_world.cells.keys() {
+                self.renderer.unload_cell(*pos);
+            }
+        }
+        self.renderer.remesh_dirty_cells(&mut world);
         self.world = Some(world);
     }
 
+    pub fn world(&self) -> Option<&World> {
+        self.world.as_ref()
+    }
+
+    pub fn world_mut(&mut self) -> Option<&mut World> {
+        self.world.as_mut()
+    }
+
+    /// Replaces the targeted voxel with `break_voxel` on a left click and places `place_voxel` against the
+    /// targeted face on a right click.
+    fn interact(renderer: &mut Renderer, world: &mut World, place_voxel: Option<usize>, break_voxel: usize, button: MouseButton) {
+        let camera = &renderer.camera;
+        let Some(hit) = world.raycast(camera.pos, camera.direction(), REACH) else { return };
+
+        match button {
+            MouseButton::Left => world.set_voxel(hit.pos, break_voxel),
+            MouseButton::Right => {
+                let Some(id) = place_voxel else { return };
+                let pos = hit.pos + Norm::OFFSETS[hit.norm as usize];
+                // Placing a voxel around the camera would trap it inside.
+                if World::voxel_pos(camera.pos) == pos {
+                    return;
+                }
+                world.set_voxel(pos, id);
+            }
+            _ => return,
+        }
+        renderer.remesh_dirty_cells(world);
+    }
+
     pub fn run(mut self) {
         let mut frame_start = Instant::now();
+        let mut stats_update = Instant::now();
         let mut forward = 0.0;
         let mut strafe = 0.0;
         let mut vertical = 0.0;
+        let mut show_cell_borders = false;
         self.window.set_cursor_visible(false);
         self.window
             .set_cursor_grab(CursorGrabMode::Confined)
@@ -157,9 +226,32 @@ impl Instance {
                             } => {
                                 vertical = 0.0;
                             }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F4),
+                                ..
+                            } => {
+                                show_cell_borders = !show_cell_borders;
+                            }
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F3),
+                                ..
+                            } => {
+                                self.renderer.render_mode = self.renderer.render_mode.next();
+                            }
 
                             _ => {}
                         },
+                        WindowEvent::MouseInput {
+                            state: ElementState::Pressed,
+                            button,
+                            ..
+                        } => {
+                            if let Some(world) = self.world.as_mut() {
+                                Self::interact(&mut self.renderer, world, self.place_voxel, self.break_voxel, button);
+                            }
+                        }
                         WindowEvent::Resized(physical_size) => {
                             self.renderer.resize(physical_size);
                         }
@@ -175,11 +267,50 @@ impl Instance {
                     self.renderer.camera.pos.x += (((self.renderer.camera.yaw.cos()) * forward - (self.renderer.camera.yaw.sin()) * strafe)) * frame_time * 180.0;
                     self.renderer.camera.pos.z += (((self.renderer.camera.yaw.sin()) * forward + (self.renderer.camera.yaw.cos()) * strafe)) * frame_time * 180.0;
                     self.renderer.camera.pos.y += vertical * frame_time * 180.0;
-                
-                    let frame = self.renderer.state.surface.0.get_current_texture().unwrap();
-                    self.renderer.render(&frame);
+
+                    self.renderer.outline_boxes.clear();
+                    if let Some(world) = self.world.as_mut() {
+                        let streamed = world.stream(self.renderer.camera.pos);
+                        for pos in streamed.unloaded {
+                            self.renderer.unload_cell(pos);
+                        }
+                        for err in streamed.errors {
+                            log::warn!("{err}");
+                        }
+                        // The borders are collected before remeshing, so cells remeshed this frame show up as dirty.
+                        if show_cell_borders {
+                            for cell in world.cells.values() {
+                                let color = match (cell.dirty, self.renderer.meshed_face_count(cell.pos)) {
+                                    (false, Some(0)) => EMPTY_CELL_COLOR,
+                                    (false, Some(_)) => MESHED_CELL_COLOR,
+                                    _ => DIRTY_CELL_COLOR,
+                                };
+                                self.renderer.outline_boxes.push(OutlineBox::cell(cell.pos, color));
+                            }
+                        }
+                        self.renderer.remesh_dirty_cells(world);
+
+                        let camera = &self.renderer.camera;
+                        // The outline is the voxel's cube, like the raycast it ignores the shape of the model.
+                        if let Some(hit) = world.raycast(camera.pos, camera.direction(), REACH) {
+                            self.renderer.outline_boxes.push(OutlineBox::voxel(hit.pos, TARGET_OUTLINE_COLOR));
+                        }
+                    }
+
+                    let frame = self.renderer.state.surface.0.as_ref().unwrap().get_current_texture().unwrap();
+                    self.renderer.render(&frame.texture.create_view(&wgpu::TextureViewDescriptor::default()));
                     frame.present();
 
+                    if stats_update.elapsed().as_secs_f32() >= 1.0 {
+                        if let Some(stats) = self.renderer.stats() {
+                            self.window.set_title(&format!(
+                                "{} | {} cells, {} faces, {} shape quads",
+                                self.window_title, stats.visible_cells, stats.face_count, stats.shape_quad_count
+                            ));
+                        }
+                        stats_update = Instant::now();
+                    }
+
                     frame_start = Instant::now();
                 }
                 Event::DeviceEvent {
@@ -196,6 +327,48 @@ impl Instance {
     }
 }
 
+/// Registers the voxels (and their textures) used by worlds, must happen before a renderer is created
+/// since the renderer uploads every added texture on creation. `Instance::new` calls this itself.
+pub fn register_voxels(register_voxels: &dyn Fn(&'static mut VoxelRegistry)) {
+    unsafe { register_voxels(&mut VOXEL_REGISTRY) };
+}
+
+/// Creates a renderer without a window that draws into an offscreen texture, read with `Renderer::render_to_image`.
+/// `force_fallback_adapter` selects a software adapter, so it also works on machines without a GPU. Fails if
+/// there's no adapter or it lacks the `REQUIRED_FEATURES`, which software adapters often do.
+pub fn headless_renderer(width: u32, height: u32, force_fallback_adapter: bool) -> Result<Renderer, String> {
+    block_on(init_headless_renderer(width, height, force_fallback_adapter))
+}
+
+/// The features the renderer draws with: indirect draws with a count for the visible cells, and lines for
+/// `RenderMode::WIREFRAME`.
+pub const REQUIRED_FEATURES: wgpu::Features = wgpu::Features::MULTI_DRAW_INDIRECT_COUNT.union(wgpu::Features::POLYGON_MODE_LINE);
+
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), String> {
+    let missing_features = REQUIRED_FEATURES - adapter.features();
+    if !missing_features.is_empty() {
+        return Err(format!("The adapter {} doesn't support {missing_features:?}!", adapter.get_info().name));
+    }
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: REQUIRED_FEATURES,
+                limits: wgpu::Limits {
+                    max_buffer_size: FACE_BUFFER_SIZE,
+                    max_storage_buffer_binding_size: FACE_BUFFER_SIZE as u32,
+                    max_compute_invocations_per_workgroup: 512,
+                    // Each texture takes up one layer of the block texture array.
+                    max_texture_array_layers: adapter.limits().max_texture_array_layers,
+                    ..Default::default()
+                },
+            },
+            None, // Trace path
+        )
+        .await
+        .map_err(|err| format!("The adapter {} can't create a device: {err}", adapter.get_info().name))
+}
+
 async fn init_renderer(window: &Window) -> Renderer {
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
         backends: wgpu::Backends::PRIMARY,
@@ -212,22 +385,7 @@ async fn init_renderer(window: &Window) -> Renderer {
         .await
         .unwrap();
 
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::empty() | wgpu::Features::MULTI_DRAW_INDIRECT_COUNT | wgpu::Features::POLYGON_MODE_LINE,
-                limits: wgpu::Limits {
-                    max_buffer_size: 402653184,
-                    max_storage_buffer_binding_size: 402653184,
-                    max_compute_invocations_per_workgroup: 512,
-                    ..Default::default()
-                },
-            },
-            None, // Trace path
-        )
-        .await
-        .unwrap();
+    let (device, queue) = request_device(&adapter).await.unwrap_or_else(|err| panic!("{err}"));
 
     let window_size = window.inner_size();
     let surface_configuration = wgpu::SurfaceConfiguration {
@@ -241,9 +399,44 @@ async fn init_renderer(window: &Window) -> Renderer {
     };
     surface.configure(&device, &surface_configuration);
     Renderer::new(RendererState {
-        surface: (surface, surface_configuration),
+        surface: (Some(surface), surface_configuration),
         adapter,
         device,
         queue,
     })
 }
+
+async fn init_headless_renderer(width: u32, height: u32, force_fallback_adapter: bool) -> Result<Renderer, String> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::PRIMARY,
+        ..Default::default()
+    });
+
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or("No adapter available for headless rendering!")?;
+
+    let (device, queue) = request_device(&adapter).await?;
+
+    // There's no surface to configure, the configuration only describes the offscreen texture.
+    let surface_configuration = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        width,
+        height,
+        present_mode: wgpu::PresentMode::Immediate,
+        alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        view_formats: vec![wgpu::TextureFormat::Rgba8Unorm],
+    };
+    Ok(Renderer::new(RendererState {
+        surface: (None, surface_configuration),
+        adapter,
+        device,
+        queue,
+    }))
+}
@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Cursor},
+    path::Path,
+};
+
+use crate::{
+    resource::texture_system::{self, TextureId},
+    voxel::{
+        voxel::Transparency,
+        voxelface::{Norm, FACE_CORNERS},
+    },
+    world::{cell::CELL_LENGTH, world::World},
+};
+
+use super::{
+    mesher::{Mesher, PackedFace, FACE_CUTOUT_MASK},
+    shape::ShapeQuad,
+};
+
+/// The texture coordinates of the corners in `FACE_CORNERS`, with v pointing up the image.
+const CORNER_TEX_COORDS: [[f32; 2]; 4] = [[0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+/// The corners of the two triangles of a quad.
+const QUAD_INDICES: [u32; 6] = [0, 1, 2, 2, 3, 0];
+
+/// The faces using one texture, as an indexed triangle list.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// The meshed faces of a world in world space, split into one mesh per `TextureId` and `Transparency`.
+#[derive(Clone, Debug, Default)]
+pub struct MeshExport {
+    pub materials: BTreeMap<(TextureId, Transparency), MaterialMesh>,
+}
+
+impl MeshExport {
+    /// Meshes every loaded cell of `world` with `mesher`.
+    pub fn from_world(world: &World, mesher: &dyn Mesher) -> Self {
+        let mut export = Self::default();
+        for cell in world.cells.values() {
+            let origin = cell.pos * CELL_LENGTH as i32;
+            let origin = [origin.x as f32, origin.y as f32, origin.z as f32];
+            let mesh = mesher.mesh(cell, &world.neighbor_cells(cell.pos));
+            for face in &mesh.opaque {
+                let transparency = if face[0] & FACE_CUTOUT_MASK != 0 { Transparency::CUTOUT } else { Transparency::OPAQUE };
+                export.push_face(*face, transparency, origin);
+            }
+            for face in &mesh.translucent {
+                export.push_face(*face, Transparency::TRANSLUCENT, origin);
+            }
+            for quad in &mesh.shapes {
+                export.push_shape_quad(*quad, origin);
+            }
+        }
+        export
+    }
+
+    /// Decodes a packed face (see `Mesher`) the same way `cell.wgsl` does and appends its quad.
+    fn push_face(&mut self, [face, shading]: PackedFace, transparency: Transparency, origin: [f32; 3]) {
+        let pos = [(face >> 10) & 31, (face >> 5) & 31, face & 31].map(|value| value as f32);
+        let norm = ((face >> 15) & 7) as usize;
+        let tex_id = (shading >> 16) as TextureId;
+        let width = ((face >> 21) & 31) as f32 + 1.0;
+        let height = ((face >> 26) & 31) as f32 + 1.0;
+        let scale = match norm {
+            0 | 3 => [width, height, 1.0],
+            1 | 4 => [1.0, height, width],
+            2 => [height, 1.0, width],
+            _ => [width, 1.0, height],
+        };
+        let normal = Norm::OFFSETS[norm];
+        let normal = [normal.x as f32, normal.y as f32, normal.z as f32];
+
+        let mesh = self.materials.entry((tex_id, transparency)).or_default();
+        let first_vertex = mesh.positions.len() as u32;
+        for (corner, tex_coord) in FACE_CORNERS[norm].iter().zip(CORNER_TEX_COORDS) {
+            mesh.positions.push([0, 1, 2].map(|axis| origin[axis] + pos[axis] + corner[axis] as f32 * scale[axis]));
+            mesh.normals.push(normal);
+            mesh.tex_coords.push([tex_coord[0] * width, tex_coord[1] * height]);
+        }
+        mesh.indices.extend(QUAD_INDICES.map(|index| first_vertex + index));
+    }
+
+    /// Decodes a `ShapeQuad` the same way `cell.wgsl` does and appends it.
+    fn push_shape_quad(&mut self, quad: ShapeQuad, origin: [f32; 3]) {
+        let corners = [quad[0], quad[1], quad[2], quad[3]]
+            .map(|corner| [(corner >> 20) & 1023, (corner >> 10) & 1023, corner & 1023].map(|value| value as f32 / 16.0));
+        let [u0, v0, u1, v1] = [0, 5, 10, 15].map(|shift| ((quad[4] >> shift) & 31) as f32 / 16.0);
+        let tex_id = (quad[5] >> 16) as TextureId;
+        // Shapes are never translucent.
+        let transparency = if quad[4] >> 31 == 1 { Transparency::CUTOUT } else { Transparency::OPAQUE };
+        // Diagonal quads have no `Norm`, so the normal comes from the winding of the corners.
+        let [a, b] = [1, 2].map(|corner| [0, 1, 2].map(|axis| corners[corner][axis] - corners[0][axis]));
+        let normal = [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]];
+        let length = normal.iter().map(|value| value * value).sum::<f32>().sqrt();
+        let normal = normal.map(|value| value / length);
+
+        let mesh = self.materials.entry((tex_id, transparency)).or_default();
+        let first_vertex = mesh.positions.len() as u32;
+        for (corner, tex_coord) in corners.iter().zip([[u0, v1], [u0, v0], [u1, v0], [u1, v1]]) {
+            mesh.positions.push([0, 1, 2].map(|axis| origin[axis] + corner[axis]));
+            mesh.normals.push(normal);
+            mesh.tex_coords.push(tex_coord);
+        }
+        mesh.indices.extend(QUAD_INDICES.map(|index| first_vertex + index));
+    }
+
+    /// Writes a binary glTF 2.0 file with the textures embedded as PNG images.
+    pub fn save_glb(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_glb()?)
+    }
+
+    pub fn to_glb(&self) -> io::Result<Vec<u8>> {
+        let mut bin: Vec<u8> = Vec::new();
+        let mut buffer_views = Vec::new();
+        let mut accessors = Vec::new();
+        let mut push_view = |bin: &mut Vec<u8>, data: &[u8], target: Option<u32>| {
+            let offset = bin.len();
+            bin.extend_from_slice(data);
+            bin.resize((bin.len() + 3) & !3, 0);
+            let target = target.map_or(String::new(), |target| format!(r#","target":{target}"#));
+            buffer_views.push(format!(r#"{{"buffer":0,"byteOffset":{offset},"byteLength":{}{target}}}"#, data.len()));
+            buffer_views.len() - 1
+        };
+
+        let mut primitives = Vec::new();
+        let mut materials = Vec::new();
+        let mut textures = Vec::new();
+        let mut images = Vec::new();
+        for ((tex_id, transparency), mesh) in &self.materials {
+            let (min, max) = bounds(&mesh.positions);
+            let tex_coords: Vec<[f32; 2]> = mesh.tex_coords.iter().map(|[u, v]| [*u, 1.0 - v]).collect();
+            let attributes = [
+                (bytemuck::cast_slice(&mesh.positions), "VEC3", format!(r#","min":{min:?},"max":{max:?}"#)),
+                (bytemuck::cast_slice(&mesh.normals), "VEC3", String::new()),
+                (bytemuck::cast_slice(&tex_coords), "VEC2", String::new()),
+            ];
+            let first_accessor = accessors.len();
+            for (data, kind, extra) in attributes {
+                let view = push_view(&mut bin, data, Some(34962));
+                accessors.push(format!(
+                    r#"{{"bufferView":{view},"componentType":5126,"count":{},"type":"{kind}"{extra}}}"#,
+                    mesh.positions.len()
+                ));
+            }
+            let view = push_view(&mut bin, bytemuck::cast_slice(&mesh.indices), Some(34963));
+            accessors.push(format!(r#"{{"bufferView":{view},"componentType":5125,"count":{},"type":"SCALAR"}}"#, mesh.indices.len()));
+
+            let base_color = match texture_system::texture_image(*tex_id) {
+                Some(image) => {
+                    let view = push_view(&mut bin, &encode_png(&image)?, None);
+                    images.push(format!(r#"{{"bufferView":{view},"mimeType":"image/png"}}"#));
+                    textures.push(format!(r#"{{"sampler":0,"source":{}}}"#, images.len() - 1));
+                    format!(r#""baseColorTexture":{{"index":{}}},"#, textures.len() - 1)
+                }
+                None => String::new(),
+            };
+            // Cutout texels are discarded below half opacity, like in `cell.wgsl`.
+            let alpha_mode = match transparency {
+                Transparency::OPAQUE => "",
+                Transparency::CUTOUT => r#","alphaMode":"MASK","alphaCutoff":0.5"#,
+                Transparency::TRANSLUCENT => r#","alphaMode":"BLEND""#,
+            };
+            materials.push(format!(
+                r#"{{"name":"{}","pbrMetallicRoughness":{{{base_color}"metallicFactor":0.0}}{alpha_mode}}}"#,
+                material_name(*tex_id, *transparency)
+            ));
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"TEXCOORD_0":{}}},"indices":{},"material":{}}}"#,
+                first_accessor,
+                first_accessor + 1,
+                first_accessor + 2,
+                first_accessor + 3,
+                materials.len() - 1
+            ));
+        }
+
+        let mut json = String::from(r#"{"asset":{"version":"2.0","generator":"raxel"},"scene":0,"#);
+        if primitives.is_empty() {
+            json.push_str(r#""scenes":[{}]"#);
+        } else {
+            write!(
+                json,
+                r#""scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"#,
+                primitives.join(","),
+                materials.join(",")
+            )
+            .unwrap();
+            // Nearest filtering and repeated wrapping, like the renderer's sampler.
+            if !images.is_empty() {
+                write!(
+                    json,
+                    r#""samplers":[{{"magFilter":9728,"minFilter":9728,"wrapS":10497,"wrapT":10497}}],"textures":[{}],"images":[{}],"#,
+                    textures.join(","),
+                    images.join(",")
+                )
+                .unwrap();
+            }
+            write!(
+                json,
+                r#""buffers":[{{"byteLength":{}}}],"bufferViews":[{}],"accessors":[{}]"#,
+                bin.len(),
+                buffer_views.join(","),
+                accessors.join(",")
+            )
+            .unwrap();
+        }
+        json.push('}');
+
+        let mut json = json.into_bytes();
+        json.resize((json.len() + 3) & !3, b' ');
+        let bin_chunk_len = if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let mut glb = Vec::with_capacity(12 + 8 + json.len() + bin_chunk_len);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + bin_chunk_len) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        if !bin.is_empty() {
+            glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(b"BIN\0");
+            glb.extend_from_slice(&bin);
+        }
+        Ok(glb)
+    }
+
+    /// Writes a Wavefront OBJ file along with a `.mtl` file of the same name, which references the
+    /// textures saved as `texture_<id>.png` next to it.
+    pub fn save_obj(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mtl_path = path.with_extension("mtl");
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut obj = String::new();
+        let mut mtl = String::new();
+        writeln!(obj, "mtllib {}", mtl_path.file_name().unwrap().to_string_lossy()).unwrap();
+        let mut first_vertex = 1;
+        for ((tex_id, transparency), mesh) in &self.materials {
+            let name = material_name(*tex_id, *transparency);
+            writeln!(mtl, "newmtl {name}\nKa 1 1 1\nKd 1 1 1\nKs 0 0 0\nd 1").unwrap();
+            if let Some(image) = texture_system::texture_image(*tex_id) {
+                let file_name = format!("texture_{tex_id}.png");
+                fs::write(dir.join(&file_name), encode_png(&image)?)?;
+                writeln!(mtl, "map_Kd {file_name}").unwrap();
+                // OBJ has no alpha modes, the texture's alpha fades both cutout and translucent faces.
+                if *transparency != Transparency::OPAQUE {
+                    writeln!(mtl, "map_d {file_name}").unwrap();
+                }
+            }
+
+            writeln!(obj, "o {name}\nusemtl {name}").unwrap();
+            for [x, y, z] in &mesh.positions {
+                writeln!(obj, "v {x} {y} {z}").unwrap();
+            }
+            for [u, v] in &mesh.tex_coords {
+                writeln!(obj, "vt {u} {v}").unwrap();
+            }
+            for [x, y, z] in &mesh.normals {
+                writeln!(obj, "vn {x} {y} {z}").unwrap();
+            }
+            for triangle in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] + first_vertex);
+                writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}").unwrap();
+            }
+            first_vertex += mesh.positions.len() as u32;
+        }
+
+        fs::write(mtl_path, mtl)?;
+        fs::write(path, obj)
+    }
+}
+
+/// The name of the material of the faces with the texture `tex_id` and `transparency`.
+fn material_name(tex_id: TextureId, transparency: Transparency) -> String {
+    match transparency {
+        Transparency::OPAQUE => format!("texture_{tex_id}"),
+        Transparency::CUTOUT => format!("texture_{tex_id}_cutout"),
+        Transparency::TRANSLUCENT => format!("texture_{tex_id}_translucent"),
+    }
+}
+
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    positions.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), pos| {
+        ([0, 1, 2].map(|axis| min[axis].min(pos[axis])), [0, 1, 2].map(|axis| max[axis].max(pos[axis])))
+    })
+}
+
+fn encode_png(image: &image::RgbaImage) -> io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mesh::greedy_mesher::GreedyMesher,
+        voxel::voxel::test_voxels::{self, GLASS, LEAVES, STONE},
+    };
+
+    use super::*;
+
+    /// A world of a single cell holding `voxels` at their local positions.
+    fn world_of(voxels: Vec<([u8; 3], usize)>) -> World {
+        test_voxels::register();
+        World::new(0, Box::new(move |cell| {
+            for ([x, y, z], id) in &voxels {
+                cell.set(*x, *y, *z, *id);
+            }
+        }))
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned_and_materials_keep_their_transparency() {
+        let world = world_of(vec![([1, 1, 1], STONE), ([5, 5, 5], GLASS), ([9, 9, 9], LEAVES)]);
+        let glb = MeshExport::from_world(&world, &GreedyMesher).to_glb().unwrap();
+
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32_at(&glb, 4), 2);
+        assert_eq!(u32_at(&glb, 8), glb.len());
+
+        let json_len = u32_at(&glb, 12);
+        assert_eq!(json_len % 4, 0);
+        assert_eq!(&glb[16..20], b"JSON");
+        let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+
+        let bin_start = 20 + json_len;
+        let bin_len = u32_at(&glb, bin_start);
+        assert_eq!(bin_len % 4, 0);
+        assert_eq!(&glb[bin_start + 4..bin_start + 8], b"BIN\0");
+        assert_eq!(bin_start + 8 + bin_len, glb.len());
+        assert_eq!(json["buffers"][0]["byteLength"], bin_len);
+
+        let mut alpha_modes: Vec<Option<&str>> =
+            json["materials"].as_array().unwrap().iter().map(|material| material["alphaMode"].as_str()).collect();
+        alpha_modes.sort();
+        assert_eq!(alpha_modes, [None, Some("BLEND"), Some("MASK")]);
+    }
+
+    #[test]
+    fn a_single_voxel_is_exported_to_obj_as_six_quads() {
+        let world = world_of(vec![([3, 4, 5], STONE)]);
+        let dir = std::env::temp_dir().join(format!("raxel-export-obj-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        MeshExport::from_world(&world, &GreedyMesher).save_obj(dir.join("voxel.obj")).unwrap();
+
+        let obj = fs::read_to_string(dir.join("voxel.obj")).unwrap();
+        let count = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+        assert_eq!(count("v "), 24);
+        assert_eq!(count("vt "), 24);
+        assert_eq!(count("vn "), 24);
+        assert_eq!(count("f "), 12);
+        assert!(fs::read_to_string(dir.join("voxel.mtl")).unwrap().contains("newmtl texture_0\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    resource::texture_system::TextureId,
+    voxel::{voxel::Transparency, voxelface::FACE_AXES},
+    world::cell::Cell,
+};
+
+use super::{
+    mesher::{face_transparency, pack_face, visible_faces, CellMesh, CellNeighbors, Mesher},
+    shape::mesh_shapes,
+};
+
+/// A rectangle of equal faces being grown by the greedy mesher.
+struct MeshingRun {
+    pub tex: TextureId,
+    pub transparency: Transparency,
+    pub shading: u32,
+    pub width: u8,
+    pub height: u8,
+}
+
+/// Merges coplanar faces with the same texture, transparency, light and occlusion into rectangles,
+/// slice by slice along each normal.
+pub struct GreedyMesher;
+
+impl Mesher for GreedyMesher {
+    fn mesh(&self, cell: &Cell, neighbors: &CellNeighbors) -> CellMesh {
+        let mut faces = visible_faces(cell, neighbors);
+
+        let mut mesh = CellMesh {
+            shapes: mesh_shapes(cell, neighbors),
+            ..Default::default()
+        };
+        for i in 0..6usize {
+            let faces = unsafe { faces.get_unchecked_mut(i) };
+            let [normal_axis, width_axis, height_axis] = unsafe { *FACE_AXES.get_unchecked(i) };
+
+            for depth in 0..32u8 {
+                let encode = |width_pos: u8, height_pos: u8| {
+                    let mut pos = [0u8; 3];
+                    pos[normal_axis] = depth;
+                    pos[width_axis] = width_pos;
+                    pos[height_axis] = height_pos;
+                    Cell::encode(pos[0], pos[1], pos[2])
+                };
+
+                for height_pos in 0..32u8 {
+                    let mut width_pos = 0u8;
+                    while width_pos < 32 {
+                        let face = unsafe { *faces.get_unchecked(encode(width_pos, height_pos)) };
+                        if face == 0 {
+                            width_pos += 1;
+                            continue;
+                        }
+
+                        let mut run = MeshingRun {
+                            tex: ((face & 0xFFF) - 1) as TextureId,
+                            transparency: face_transparency(face),
+                            shading: face >> 16,
+                            width: 1,
+                            height: 1,
+                        };
+                        while width_pos + run.width < 32
+                            && unsafe { *faces.get_unchecked(encode(width_pos + run.width, height_pos)) } == face
+                        {
+                            run.width += 1;
+                        }
+                        'grow_height: while height_pos + run.height < 32 {
+                            for offset in 0..run.width {
+                                if unsafe { *faces.get_unchecked(encode(width_pos + offset, height_pos + run.height)) } != face {
+                                    break 'grow_height;
+                                }
+                            }
+                            run.height += 1;
+                        }
+
+                        for height_offset in 0..run.height {
+                            for width_offset in 0..run.width {
+                                unsafe { *faces.get_unchecked_mut(encode(width_pos + width_offset, height_pos + height_offset)) = 0 };
+                            }
+                        }
+
+                        mesh.push(
+                            pack_face(encode(width_pos, height_pos), i, run.tex as u32, run.width, run.height, run.shading, run.transparency),
+                            run.transparency,
+                        );
+                        width_pos += run.width;
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ultraviolet::IVec3;
+
+    use crate::voxel::voxel::test_voxels::{self, DIRT, STONE};
+
+    use super::{super::mesher::tests::unit_faces, *};
+
+    #[test]
+    fn a_slab_merges_into_one_quad_per_side() {
+        test_voxels::register();
+        let mut cell = Cell::new(IVec3::zero());
+        for x in 0..32 {
+            for z in 0..32 {
+                cell.set(x, 5, z, STONE);
+            }
+        }
+        let mesh = GreedyMesher.mesh(&cell, &[None; 6]);
+        assert_eq!(mesh.opaque.len(), 6);
+        assert_eq!(unit_faces(&mesh).len(), 2 * 32 * 32 + 4 * 32);
+
+        // A different voxel in the slab splits the quads it lies in.
+        cell.set(0, 5, 0, DIRT);
+        assert!(GreedyMesher.mesh(&cell, &[None; 6]).opaque.len() > 6);
+    }
+}
@@ -0,0 +1,363 @@
+use std::simd::{ SimdPartialEq, simd_swizzle, usizex4, Which::* };
+
+use crate::{
+    voxel::{
+        voxel::Transparency,
+        voxelface::{Norm, FACE_AXES, FACE_CORNERS},
+        voxelmodel::VoxelModel,
+    },
+    world::{
+        cell::{Cell, CELL_SIZE, CELL_X_MASK, CELL_Y_MASK, CELL_Z_MASK},
+        light::MAX_LIGHT,
+    },
+    VOXEL_REGISTRY,
+};
+
+use super::shape::ShapeQuad;
+
+/// The loaded cells adjacent to a cell, in the order of `Norm::VALUES`.
+pub type CellNeighbors<'a> = [Option<&'a Cell>; 6];
+
+/// A face as drawn by `cell.wgsl`. In the first word bits 0-14 hold the local position (`Cell::encode`),
+/// 15-17 the `Norm`, 21-25 and 26-30 the width and height of the quad minus one and bit 31 whether the
+/// face is a `Transparency::CUTOUT` face, bits 18-20 are unused. In the second word bits 0-3 hold the sky
+/// light and 4-7 the block light in front of the face, bits 8-15 the ambient occlusion of its corners
+/// (see `face_occlusion`) and bits 16-31 the `TextureId`.
+pub type PackedFace = [u32; 2];
+
+/// The bit of the first word of a `PackedFace` marking a `Transparency::CUTOUT` face.
+pub const FACE_CUTOUT_MASK: u32 = 1 << 31;
+
+/// The neighbor past the negative and the positive border of the cell along each axis.
+const BORDER_NEIGHBORS: [[usize; 2]; 3] = [
+    [Norm::WEST as usize, Norm::EAST as usize],
+    [Norm::DOWN as usize, Norm::UP as usize],
+    [Norm::NORTH as usize, Norm::SOUTH as usize],
+];
+
+/// The faces of a cell, split by the pass they're drawn in.
+#[derive(Clone, Debug, Default)]
+pub struct CellMesh {
+    /// The opaque and cutout faces, drawn first with depth writes.
+    pub opaque: Vec<PackedFace>,
+    /// The translucent faces, blended over the opaque faces back to front.
+    pub translucent: Vec<PackedFace>,
+    /// The quads of the voxels that aren't cubes, see `mesh_shapes`.
+    pub shapes: Vec<ShapeQuad>,
+}
+
+impl CellMesh {
+    pub fn push(&mut self, face: PackedFace, transparency: Transparency) {
+        match transparency {
+            Transparency::TRANSLUCENT => self.translucent.push(face),
+            _ => self.opaque.push(face),
+        }
+    }
+
+    /// The amount of cube faces and shape quads.
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.translucent.len() + self.shapes.len()
+    }
+
+    /// Whether the mesh has neither cube faces nor shape quads, so there's nothing to draw.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cube faces of both passes.
+    pub fn faces(&self) -> impl Iterator<Item = &PackedFace> {
+        self.opaque.iter().chain(&self.translucent)
+    }
+
+    /// Leaves out the faces of the opaque pass past `max_faces` and returns how many were left out.
+    /// Fully opaque faces are kept first, so that only cutout faces are lost unless there are more
+    /// opaque faces than `max_faces` on their own.
+    pub fn truncate_opaque(&mut self, max_faces: usize) -> usize {
+        let dropped = self.opaque.len().saturating_sub(max_faces);
+        if dropped > 0 {
+            let (mut opaque, cutout): (Vec<_>, Vec<_>) =
+                self.opaque.drain(..).partition(|[face, _]| face & FACE_CUTOUT_MASK == 0);
+            opaque.extend(cutout);
+            opaque.truncate(max_faces);
+            self.opaque = opaque;
+        }
+        dropped
+    }
+}
+
+/// Turns the voxels of a cell into packed faces, each drawn as one quad, and shape quads.
+pub trait Mesher {
+    fn mesh(&self, cell: &Cell, neighbors: &CellNeighbors) -> CellMesh;
+}
+
+#[inline(always)]
+pub fn pack_face(pos: usize, norm: usize, tex: u32, width: u8, height: u8, shading: u32, transparency: Transparency) -> PackedFace {
+    [
+        (pos as u32)
+            | ((norm as u32) << 15)
+            | (((width - 1) as u32) << 21)
+            | (((height - 1) as u32) << 26)
+            | if transparency == Transparency::CUTOUT { FACE_CUTOUT_MASK } else { 0 },
+        (shading & 0xFFFF) | (tex << 16),
+    ]
+}
+
+/// The centre of a packed face in cell space.
+pub fn face_center(face: PackedFace) -> [f32; 3] {
+    let [face, _] = face;
+    let norm = ((face >> 15) & 7) as usize;
+    let [normal_axis, width_axis, height_axis] = FACE_AXES[norm];
+    let mut center = [(face >> 10) & 31, (face >> 5) & 31, face & 31].map(|value| value as f32 + 0.5);
+    center[normal_axis] += Norm::OFFSETS[norm][normal_axis] as f32 * 0.5;
+    center[width_axis] += ((face >> 21) & 31) as f32 * 0.5;
+    center[height_axis] += ((face >> 26) & 31) as f32 * 0.5;
+    center
+}
+
+/// The `Transparency` of a face from `visible_faces`.
+#[inline(always)]
+pub fn face_transparency(face: u32) -> Transparency {
+    unsafe { *Transparency::VALUES.get_unchecked(((face >> 12) & 3) as usize) }
+}
+
+/// Whether the voxel at the local position `pos`, which may lie one voxel past the border of the cell, is opaque.
+/// Voxels past an edge or a corner of the cell lie in diagonal neighbors that aren't available and count as empty.
+pub fn is_opaque(cell: &Cell, neighbors: &CellNeighbors, pos: [i32; 3]) -> bool {
+    let mut source = Some(cell);
+    let mut outside = 0;
+    for axis in 0..3 {
+        if !(0..32).contains(&pos[axis]) {
+            outside += 1;
+            source = neighbors[BORDER_NEIGHBORS[axis][(pos[axis] > 0) as usize]];
+        }
+    }
+    if outside > 1 {
+        return false;
+    }
+    let [x, y, z] = pos.map(|value| value.rem_euclid(32) as u8);
+    source.is_some_and(|source| unsafe { VOXEL_REGISTRY.get(source.get(x, y, z)) }.is_opaque())
+}
+
+/// The ambient occlusion of the corners of the face `norm` of the voxel at `pos`, two bits per corner in
+/// the order of `FACE_CORNERS`. Each corner is darkened by the opaque voxels among the two voxels beside
+/// it and the one diagonal to it in front of the face, from 3 for an open corner to 0 for an enclosed one.
+pub fn face_occlusion(cell: &Cell, neighbors: &CellNeighbors, pos: [i32; 3], norm: usize) -> u32 {
+    let normal_axis = FACE_AXES[norm][0];
+    let [side_axis, other_side_axis] = [(normal_axis + 1) % 3, (normal_axis + 2) % 3];
+    let offset = Norm::OFFSETS[norm];
+    let front = [pos[0] + offset.x, pos[1] + offset.y, pos[2] + offset.z];
+
+    let mut occlusion = 0;
+    for (i, corner) in FACE_CORNERS[norm].iter().enumerate() {
+        let mut side = front;
+        side[side_axis] += corner[side_axis] as i32 * 2 - 1;
+        let mut other_side = front;
+        other_side[other_side_axis] += corner[other_side_axis] as i32 * 2 - 1;
+        let mut diagonal = side;
+        diagonal[other_side_axis] = other_side[other_side_axis];
+
+        let [side, other_side, diagonal] = [side, other_side, diagonal].map(|pos| is_opaque(cell, neighbors, pos) as u32);
+        let corner_occlusion = if side == 1 && other_side == 1 { 0 } else { 3 - side - other_side - diagonal };
+        occlusion |= corner_occlusion << (i * 2);
+    }
+    occlusion
+}
+
+/// The light of the voxels next to unloaded cells, which are treated as open sky.
+const UNLOADED_LIGHT: u32 = MAX_LIGHT as u32;
+
+/// Finds the faces of every voxel that aren't hidden by an opaque neighbor or by a neighbor of the same
+/// transparent voxel, indexed by `Norm` and `Cell::encode`. A visible face holds its `TextureId` plus one
+/// in bits 0-11 (see `MAX_TEXTURES`), its `Transparency` in bits 12-13 and the light and occlusion of its
+/// `PackedFace` in the high 16 bits, so equal faces also match in transparency, light and occlusion. A
+/// hidden face holds zero.
+pub fn visible_faces(cell: &Cell, neighbors: &CellNeighbors) -> Box<[[u32; CELL_SIZE]; 6]> {
+    let mut faces: Box<[[u32; CELL_SIZE]; 6]> = vec![[0; CELL_SIZE]; 6].into_boxed_slice().try_into().unwrap();
+
+    for z in 0..32 { for y in 0..32 { for x in 0..32 {
+        let idx = Cell::encode(x, y, z);
+        let voxel_id = cell.voxels.get(idx);
+        let voxel = unsafe { VOXEL_REGISTRY.get(voxel_id) };
+        // Other models are meshed into the shape stream by `mesh_shapes`.
+        let Some(VoxelModel::CUBE(cube)) = &voxel.model else { continue };
+        let transparency = voxel.transparency;
+
+        const NEIGHBOR_MASKS: usizex4 = usizex4::from_array([CELL_Z_MASK, CELL_X_MASK, CELL_Y_MASK, 0]);
+        const NEIGHBOR_DECODING_MASKS: usizex4 = usizex4::from_array([31, 31, 31, 0]);
+        const NEIGHBOR_DECODING: usizex4 = usizex4::from_array([0, 10, 5, 0]);
+        let idx_splat = usizex4::splat(idx);
+        let invalid_negative_neighbors = ((idx_splat >> NEIGHBOR_DECODING) & NEIGHBOR_DECODING_MASKS).simd_eq(usizex4::splat(0));
+        let invalid_positive_neighbors = ((idx_splat >> NEIGHBOR_DECODING) & NEIGHBOR_DECODING_MASKS).simd_eq(usizex4::splat(31));
+
+        let invalid_neighbors = simd_swizzle!(invalid_negative_neighbors.to_int(), invalid_positive_neighbors.to_int(), [
+            First(0),
+            First(1),
+            First(2),
+            Second(0),
+            Second(1),
+            Second(2),
+            Second(3),
+            Second(3),
+        ]);
+
+        let neighbor_indices = simd_swizzle!(idx_splat - NEIGHBOR_MASKS, idx_splat + NEIGHBOR_MASKS, [
+            First(0),
+            First(1),
+            First(2),
+            Second(0),
+            Second(1),
+            Second(2),
+            Second(3),
+            Second(3),
+        ]);
+
+        for i in 0..6usize {
+            let face = unsafe { *cube.get_unchecked(i) };
+            let neighbor_index = neighbor_indices[i];
+
+            // Neighbors past the border of the cell are looked up in the adjacent cell, where the
+            // coordinate along the face normal wraps around to the opposite border.
+            let (neighbor_id, light) = if invalid_neighbors[i] == 0 {
+                (Some(cell.voxels.get(neighbor_index)), cell.light.packed(neighbor_index) as u32)
+            } else {
+                const BORDER_MASKS: [usize; 6] = [CELL_Z_MASK * 31, CELL_X_MASK * 31, CELL_Y_MASK * 31, CELL_Z_MASK * 31, CELL_X_MASK * 31, CELL_Y_MASK * 31];
+                match unsafe { neighbors.get_unchecked(i) } {
+                    Some(neighbor_cell) => (
+                        Some(neighbor_cell.voxels.get(idx ^ BORDER_MASKS[i])),
+                        neighbor_cell.light.packed(idx ^ BORDER_MASKS[i]) as u32,
+                    ),
+                    None => (None, UNLOADED_LIGHT),
+                }
+            };
+            // Transparent voxels only hide the faces between each other when they're the same voxel, so
+            // a pane of glass shows no inner faces while the stone behind it stays visible.
+            if neighbor_id.is_some_and(|neighbor_id| {
+                unsafe { VOXEL_REGISTRY.get(neighbor_id) }.is_opaque() || (transparency != Transparency::OPAQUE && neighbor_id == voxel_id)
+            }) {
+                continue;
+            }
+
+            let occlusion = face_occlusion(cell, neighbors, [x as i32, y as i32, z as i32], i);
+            unsafe { *faces.get_unchecked_mut(i).get_unchecked_mut(idx) = (face.tex_id as u32 + 1) | ((transparency as u32) << 12) | (light << 16) | (occlusion << 24) };
+        }
+    }}}
+
+    faces
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeSet;
+
+    use ultraviolet::IVec3;
+
+    use crate::voxel::voxel::test_voxels::{self, AIR, DIRT, GLASS, LEAVES, SLAB, STONE};
+
+    use super::{super::{greedy_mesher::GreedyMesher, naive_mesher::NaiveMesher}, *};
+
+    /// Splits the faces of a mesh into the faces of single voxels, as their position, `Norm` and
+    /// second word, along with whether they're cutout faces.
+    pub fn unit_faces(mesh: &CellMesh) -> BTreeSet<([u32; 3], usize, u32, bool)> {
+        let mut unit_faces = BTreeSet::new();
+        for [face, shading] in mesh.faces() {
+            let pos = [(face >> 10) & 31, (face >> 5) & 31, face & 31];
+            let norm = ((face >> 15) & 7) as usize;
+            let [_, width_axis, height_axis] = FACE_AXES[norm];
+            for width_offset in 0..=(face >> 21) & 31 {
+                for height_offset in 0..=(face >> 26) & 31 {
+                    let mut pos = pos;
+                    pos[width_axis] += width_offset;
+                    pos[height_axis] += height_offset;
+                    assert!(unit_faces.insert((pos, norm, *shading, face & FACE_CUTOUT_MASK != 0)), "two faces overlap at {pos:?}");
+                }
+            }
+        }
+        unit_faces
+    }
+
+    /// A cell of random voxels, from a small linear congruential generator.
+    fn random_cell(seed: u64, ids: &[usize]) -> Cell {
+        let mut state = seed;
+        let mut cell = Cell::new(IVec3::zero());
+        for idx in 0..CELL_SIZE {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            cell.voxels.set(idx, ids[(state >> 33) as usize % ids.len()]);
+        }
+        cell
+    }
+
+    const MESHERS: [&dyn Mesher; 2] = [&GreedyMesher, &NaiveMesher];
+
+    #[test]
+    fn a_single_voxel_has_six_faces() {
+        test_voxels::register();
+        for mesher in MESHERS {
+            let mut cell = Cell::new(IVec3::zero());
+            assert!(mesher.mesh(&cell, &[None; 6]).is_empty());
+            cell.set(7, 8, 9, STONE);
+            let mesh = mesher.mesh(&cell, &[None; 6]);
+            assert_eq!(mesh.opaque.len(), 6);
+            assert_eq!(unit_faces(&mesh).len(), 6);
+        }
+    }
+
+    #[test]
+    fn border_faces_are_culled_against_the_neighbor_cell() {
+        test_voxels::register();
+        for mesher in MESHERS {
+            // A voxel against the positive and one against the negative border of the cell.
+            for (local, norm, neighbor_local) in [([31, 4, 4], Norm::EAST, [0, 4, 4]), ([4, 4, 0], Norm::NORTH, [4, 4, 31])] {
+                let mut cell = Cell::new(IVec3::zero());
+                cell.set(local[0], local[1], local[2], STONE);
+                let mut neighbor = Cell::new(Norm::OFFSETS[norm as usize]);
+                neighbor.set(neighbor_local[0], neighbor_local[1], neighbor_local[2], STONE);
+
+                let mut neighbors = [None; 6];
+                neighbors[norm as usize] = Some(&neighbor);
+                let faces = unit_faces(&mesher.mesh(&cell, &neighbors));
+                assert_eq!(faces.len(), 5);
+                assert!(faces.iter().all(|(_, face_norm, _, _)| *face_norm != norm as usize));
+
+                // Without the neighbor the face past the border is drawn.
+                assert_eq!(unit_faces(&mesher.mesh(&cell, &[None; 6])).len(), 6);
+            }
+        }
+    }
+
+    #[test]
+    fn greedy_and_naive_meshers_cover_the_same_faces() {
+        test_voxels::register();
+        for seed in 0..4 {
+            // Mostly air, so the greedy mesher has runs to merge.
+            let cell = random_cell(seed, &[AIR, AIR, AIR, AIR, STONE, STONE, DIRT, GLASS, LEAVES, SLAB]);
+            let neighbor = random_cell(seed + 100, &[AIR, STONE]);
+            let neighbors = [None, Some(&neighbor), None, Some(&neighbor), None, Some(&neighbor)];
+
+            let greedy = GreedyMesher.mesh(&cell, &neighbors);
+            let naive = NaiveMesher.mesh(&cell, &neighbors);
+            assert!(greedy.len() < naive.len());
+            assert_eq!(unit_faces(&greedy), unit_faces(&naive));
+            assert_eq!(greedy.translucent.is_empty(), naive.translucent.is_empty());
+            assert!(!greedy.shapes.is_empty());
+            assert_eq!(greedy.shapes, naive.shapes);
+        }
+    }
+
+    #[test]
+    fn truncating_the_opaque_faces_leaves_out_cutout_faces_first() {
+        let max_faces = 64;
+        let mut mesh = CellMesh::default();
+        // Alternate cutout and opaque faces, with fewer opaque faces than fit.
+        for pos in 0..max_faces + 16 {
+            let transparency = if pos % 3 == 0 { Transparency::OPAQUE } else { Transparency::CUTOUT };
+            mesh.push(pack_face(pos, 0, 0, 1, 1, 0, transparency), transparency);
+        }
+        let opaque_faces: Vec<PackedFace> = mesh.opaque.iter().copied().filter(|[face, _]| face & FACE_CUTOUT_MASK == 0).collect();
+
+        assert_eq!(mesh.truncate_opaque(max_faces), 16);
+        assert_eq!(mesh.opaque.len(), max_faces);
+        assert_eq!(mesh.opaque[..opaque_faces.len()], opaque_faces);
+        assert!(mesh.opaque[opaque_faces.len()..].iter().all(|[face, _]| face & FACE_CUTOUT_MASK != 0));
+        assert_eq!(mesh.truncate_opaque(max_faces), 0);
+    }
+}
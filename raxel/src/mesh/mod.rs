@@ -0,0 +1,5 @@
+pub mod export;
+pub mod greedy_mesher;
+pub mod mesher;
+pub mod naive_mesher;
+pub mod shape;
@@ -0,0 +1,82 @@
+use std::simd::u32x16;
+
+use crate::world::cell::Cell;
+
+use super::{
+    mesher::{face_transparency, visible_faces, CellMesh, CellNeighbors, Mesher},
+    shape::mesh_shapes,
+};
+
+/// Emits every visible face as its own quad, packing 16 faces at a time.
+pub struct NaiveMesher;
+
+impl Mesher for NaiveMesher {
+    fn mesh(&self, cell: &Cell, neighbors: &CellNeighbors) -> CellMesh {
+        let faces = visible_faces(cell, neighbors);
+
+        let mut mesh = CellMesh {
+            shapes: mesh_shapes(cell, neighbors),
+            ..Default::default()
+        };
+        for i in 0..6usize {
+            let faces = unsafe { faces.get_unchecked(i) };
+
+            const CHUNK_SIZE: usize = 16;
+            let mut chunk_index = 0;
+            for chunk in faces.array_chunks::<CHUNK_SIZE>() {
+                let arr = u32x16::from_array(*chunk);
+                let mut chunk_mesh = u32x16::from_array([
+                    chunk_index + 0,
+                    chunk_index + 1,
+                    chunk_index + 2,
+                    chunk_index + 3,
+                    chunk_index + 4,
+                    chunk_index + 5,
+                    chunk_index + 6,
+                    chunk_index + 7,
+                    chunk_index + 8,
+                    chunk_index + 9,
+                    chunk_index + 10,
+                    chunk_index + 11,
+                    chunk_index + 12,
+                    chunk_index + 13,
+                    chunk_index + 14,
+                    chunk_index + 15,
+                ]);
+
+                chunk_mesh |= u32x16::splat((i as u32) << 15);
+                // Bit 12 is set for cutout faces, see `visible_faces`.
+                chunk_mesh |= ((arr >> u32x16::splat(12)) & u32x16::splat(1)) << u32x16::splat(31);
+                let chunk_shading = (arr >> u32x16::splat(16)) | (((arr & u32x16::splat(0xFFF)) - u32x16::splat(1)) << u32x16::splat(16));
+
+                for j in 0..16usize {
+                    if chunk[j] != 0 { mesh.push([chunk_mesh[j], chunk_shading[j]], face_transparency(chunk[j])); }
+                }
+
+                chunk_index += CHUNK_SIZE as u32;
+            }
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ultraviolet::IVec3;
+
+    use crate::voxel::voxel::test_voxels::{self, STONE};
+
+    use super::*;
+
+    #[test]
+    fn a_slab_is_meshed_face_by_face() {
+        test_voxels::register();
+        let mut cell = Cell::new(IVec3::zero());
+        for x in 0..32 {
+            for z in 0..32 {
+                cell.set(x, 5, z, STONE);
+            }
+        }
+        assert_eq!(NaiveMesher.mesh(&cell, &[None; 6]).opaque.len(), 2 * 32 * 32 + 4 * 32);
+    }
+}
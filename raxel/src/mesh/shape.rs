@@ -0,0 +1,101 @@
+use crate::{
+    voxel::{
+        voxel::Transparency,
+        voxelface::{Norm, FACE_CORNERS},
+        voxelmodel::{VoxelModel, MODEL_UNITS},
+    },
+    world::cell::{Cell, CELL_SIZE},
+    VOXEL_REGISTRY,
+};
+
+use super::mesher::{is_opaque, CellNeighbors};
+
+/// A quad of a non-cube `VoxelModel` as drawn by `cell.wgsl`'s `vert_shape`. Words 0-3 hold the corners
+/// in the order of `FACE_CORNERS`, in sixteenths of a voxel in cell space with x in bits 20-29, y in 10-19
+/// and z in 0-9. Word 4 holds the texture rectangle u0, v0, u1 and v1 in sixteenths in bits 0-4, 5-9,
+/// 10-14 and 15-19, the `Norm` in bits 20-22 (`DIAGONAL_NORM` for quads that aren't axis aligned) and
+/// whether the quad is a cutout in bit 31. Word 5 holds the light like a `PackedFace` and the `TextureId`
+/// in bits 16-31.
+pub type ShapeQuad = [u32; 6];
+
+/// The normal of the diagonal quads of `VoxelModel::CROSS`.
+pub const DIAGONAL_NORM: u32 = 6;
+/// The occlusion of the quads' corners, shapes aren't occluded.
+const OPEN_OCCLUSION: u32 = 0xFF << 8;
+
+/// The corners of the two planes of `VoxelModel::CROSS`, in sixteenths of a voxel.
+const CROSS_CORNERS: [[[u32; 3]; 4]; 2] = [
+    [[0, 16, 0], [0, 0, 0], [16, 0, 16], [16, 16, 16]],
+    [[16, 16, 0], [16, 0, 0], [0, 0, 16], [0, 16, 16]],
+];
+
+fn pack_corner(corner: [u32; 3]) -> u32 {
+    (corner[0] << 20) | (corner[1] << 10) | corner[2]
+}
+
+fn pack_quad(corners: [[u32; 3]; 4], tex_rect: [u32; 4], norm: u32, cutout: bool, shading: u32, tex_id: u32) -> ShapeQuad {
+    let [c0, c1, c2, c3] = corners.map(pack_corner);
+    [
+        c0,
+        c1,
+        c2,
+        c3,
+        tex_rect[0] | (tex_rect[1] << 5) | (tex_rect[2] << 10) | (tex_rect[3] << 15) | (norm << 20) | ((cutout as u32) << 31),
+        (shading & 0xFFFF) | (tex_id << 16),
+    ]
+}
+
+/// Meshes the voxels of a cell whose model isn't a cube. Faces of boxes are left out when the opaque
+/// neighbor on their cull side hides them, everything else is always drawn. Shapes are drawn in the opaque
+/// pass, `VoxelRegistry::register` rejects translucent shapes.
+pub fn mesh_shapes(cell: &Cell, neighbors: &CellNeighbors) -> Vec<ShapeQuad> {
+    let mut quads = Vec::new();
+    let has_shapes = cell
+        .voxels
+        .palette()
+        .iter()
+        .any(|id| unsafe { VOXEL_REGISTRY.get(*id) }.model.as_ref().is_some_and(|model| !model.is_cube()));
+    if !has_shapes {
+        return quads;
+    }
+
+    for idx in 0..CELL_SIZE {
+        let voxel = unsafe { VOXEL_REGISTRY.get(cell.voxels.get(idx)) };
+        let pos = [(idx >> 10) as u32, ((idx >> 5) & 31) as u32, (idx & 31) as u32];
+        let origin = pos.map(|value| value * MODEL_UNITS as u32);
+        let cutout = voxel.transparency != Transparency::OPAQUE;
+        let shading = cell.light.packed(idx) as u32 | OPEN_OCCLUSION;
+
+        match &voxel.model {
+            Some(VoxelModel::BOXES(boxes)) => {
+                for model_box in boxes {
+                    for (norm, face) in model_box.faces.iter().enumerate() {
+                        let Some(face) = face else { continue };
+                        if let Some(cull) = face.cull {
+                            let offset = Norm::OFFSETS[cull as usize];
+                            if is_opaque(cell, neighbors, [pos[0] as i32 + offset.x, pos[1] as i32 + offset.y, pos[2] as i32 + offset.z]) {
+                                continue;
+                            }
+                        }
+
+                        let [min, max] = [model_box.min, model_box.max].map(|corner| corner.map(|value| value as u32));
+                        let corners = FACE_CORNERS[norm].map(|corner| [0, 1, 2].map(|axis| origin[axis] + if corner[axis] == 0 { min[axis] } else { max[axis] }));
+                        quads.push(pack_quad(corners, face.uv.map(|value| value as u32), norm as u32, cutout, shading, face.tex_id as u32));
+                    }
+                }
+            }
+            Some(VoxelModel::CROSS(tex_id)) => {
+                // Both sides of each plane are drawn, as the back faces are culled.
+                for corners in CROSS_CORNERS {
+                    let corners = corners.map(|corner| [0, 1, 2].map(|axis| origin[axis] + corner[axis]));
+                    let [c0, c1, c2, c3] = corners;
+                    for corners in [corners, [c3, c2, c1, c0]] {
+                        quads.push(pack_quad(corners, [0, 0, 16, 16], DIAGONAL_NORM, true, shading, *tex_id as u32));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    quads
+}
@@ -1,4 +1,7 @@
 pub mod bindable;
 pub mod block_buffer;
 pub mod camera;
+pub mod outline;
+pub mod render_mode;
 pub mod renderer;
+pub mod stats;
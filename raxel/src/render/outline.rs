@@ -0,0 +1,139 @@
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::IVec3;
+
+use crate::{
+    resource::resource::{LoadedResource, ResourceId, ResourceLoader},
+    world::cell::CELL_LENGTH,
+};
+
+use super::{
+    bindable::{Bindable, BindableBuffer},
+    renderer::RendererState,
+};
+
+/// The amount of boxes that can be outlined in a frame.
+pub const MAX_OUTLINE_BOXES: usize = 1024;
+/// The vertices of a box outline, two for each of the 12 edges.
+const OUTLINE_BOX_VERTICES: u32 = 24;
+
+/// A box drawn as lines around its edges by `outline.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
+pub struct OutlineBox {
+    pub min: [f32; 3],
+    pub size: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl OutlineBox {
+    /// A box around the voxel at `pos`.
+    pub fn voxel(pos: IVec3, color: [f32; 4]) -> Self {
+        Self {
+            min: [pos.x as f32, pos.y as f32, pos.z as f32],
+            size: [1.0; 3],
+            color,
+        }
+    }
+
+    /// A box around the borders of the cell at `pos`.
+    pub fn cell(pos: IVec3, color: [f32; 4]) -> Self {
+        let min = pos * CELL_LENGTH as i32;
+        Self {
+            min: [min.x as f32, min.y as f32, min.z as f32],
+            size: [CELL_LENGTH as f32; 3],
+            color,
+        }
+    }
+}
+
+/// Draws `OutlineBox`es on top of the cells, depth tested against them but without writing depth.
+pub struct OutlinePipeline {
+    pipeline: wgpu::RenderPipeline,
+    box_buffer: wgpu::Buffer,
+}
+
+impl OutlinePipeline {
+    pub fn new(state: &RendererState, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let layout = state
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[camera_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let LoadedResource::SHADER(outline_shader_source) = ResourceId(Some(String::from("shader")), String::from("outline.wgsl")).load(ResourceLoader::SHADER) else { unreachable!() };
+        let module = state
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: outline_shader_source,
+            });
+        let pipeline = state
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vert",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: core::mem::size_of::<OutlineBox>() as u64,
+                        step_mode: wgpu::VertexStepMode::Instance,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x4],
+                    }],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: "frag",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: state.surface.1.view_formats[0],
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: wgpu::TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            });
+
+        let box_buffer = state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (core::mem::size_of::<OutlineBox>() * MAX_OUTLINE_BOXES) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { pipeline, box_buffer }
+    }
+
+    /// Uploads `boxes` and records their draw, boxes past `MAX_OUTLINE_BOXES` are left out.
+    pub fn draw<'a>(
+        &'a self,
+        queue: &wgpu::Queue,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera_buffer: &'a BindableBuffer,
+        boxes: &[OutlineBox],
+    ) {
+        let boxes = &boxes[..boxes.len().min(MAX_OUTLINE_BOXES)];
+        if boxes.is_empty() {
+            return;
+        }
+        queue.write_buffer(&self.box_buffer, 0, bytemuck::cast_slice(boxes));
+
+        pass.set_pipeline(&self.pipeline);
+        camera_buffer.bind(0, pass);
+        pass.set_vertex_buffer(0, self.box_buffer.slice(..));
+        pass.draw(0..OUTLINE_BOX_VERTICES, 0..boxes.len() as u32);
+    }
+}
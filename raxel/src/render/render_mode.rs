@@ -0,0 +1,48 @@
+/// How the cells are drawn, each mode has its own variant of the cell pipeline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// The textured and shaded faces.
+    #[default]
+    TEXTURED = 0,
+    /// The edges of every face's triangles.
+    WIREFRAME = 1,
+    /// Faces coloured by their `Norm`.
+    NORMALS = 2,
+    /// Faces coloured by the cell they belong to.
+    CELLS = 3,
+    /// Faces coloured by the size of their quad, from single voxels in blue to large merged quads in red.
+    QUADS = 4,
+}
+
+impl RenderMode {
+    pub const VALUES: [Self; 5] = [
+        RenderMode::TEXTURED,
+        RenderMode::WIREFRAME,
+        RenderMode::NORMALS,
+        RenderMode::CELLS,
+        RenderMode::QUADS,
+    ];
+
+    /// The fragment entry point of `cell.wgsl` used by the mode.
+    pub fn fragment_entry_point(&self) -> &'static str {
+        match self {
+            RenderMode::TEXTURED => "frag",
+            RenderMode::WIREFRAME => "frag_wireframe",
+            RenderMode::NORMALS => "frag_normal",
+            RenderMode::CELLS => "frag_cell",
+            RenderMode::QUADS => "frag_quad_size",
+        }
+    }
+
+    pub fn polygon_mode(&self) -> wgpu::PolygonMode {
+        match self {
+            RenderMode::WIREFRAME => wgpu::PolygonMode::Line,
+            _ => wgpu::PolygonMode::Fill,
+        }
+    }
+
+    /// The mode after this one, wrapping around to the first.
+    pub fn next(&self) -> Self {
+        Self::VALUES[(*self as usize + 1) % Self::VALUES.len()]
+    }
+}
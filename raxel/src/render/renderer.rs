@@ -1,76 +1,153 @@
-use std::simd::{ SimdPartialEq, simd_swizzle, usizex4, Which::*, u32x16 };
+use std::{borrow::Cow, cell::Cell as InteriorCell, collections::HashMap, num::NonZeroU32, sync::mpsc};
 
-use ultraviolet::Mat4;
+use ultraviolet::{IVec3, Mat4, Vec3};
 use winit::dpi::PhysicalSize;
 
 use crate::{
+    mesh::{
+        greedy_mesher::GreedyMesher,
+        mesher::{face_center, Mesher, PackedFace},
+    },
     resource::{
         resource::{LoadedResource, ResourceId, ResourceLoader},
-        texture_system::{create_texture_array, TextureId},
+        texture_system::create_texture_array,
     },
     world::{
         cell::{
-            Cell, CELL_BUFFER_SIZE, CELL_SIZE, CELL_Z_MASK, CELL_X_MASK, CELL_Y_MASK,
+            Cell, CELL_BUFFER_SIZE, CELL_LENGTH, MAX_CELL_FACES, MAX_CELL_SHAPE_QUADS, MAX_TRANSLUCENT_CELL_FACES,
+            SHAPE_CELL_BUFFER_SIZE, TRANSLUCENT_CELL_BUFFER_SIZE,
         },
-        world::{World, WORLD_LENGTH, WORLD_SIZE},
+        world::{World, MAX_LOADED_CELLS},
     },
-    VOXEL_REGISTRY,
 };
 
 use super::{
     bindable::{Bindable, BindableBuffer},
     block_buffer::BlockBuffer,
     camera::Camera,
+    outline::{OutlineBox, OutlinePipeline},
+    render_mode::RenderMode,
+    stats::{RenderStats, StatsReadback, DRAW_COUNTS_SIZE, SHAPE_DRAW_COUNT_OFFSET, TRANSLUCENT_DRAW_COUNT_OFFSET},
 };
 
+/// Cell positions are uploaded as `vec4<i32>` to satisfy the storage buffer alignment.
+const CELL_POS_STRIDE: usize = core::mem::size_of::<[i32; 4]>();
+
+/// The constants the shaders share with the renderer, declared in front of the shader sources so they
+/// can't drift apart.
+fn shader_constants() -> String {
+    format!(
+        "const CELL_LENGTH: f32 = {}.0;\n\
+         const MAX_LOADED_CELLS: u32 = {}u;\n\
+         // The vertices of a slot of the face buffer and of the shape buffer, six per face or quad.\n\
+         const CELL_VERTEX_STRIDE: u32 = {}u;\n\
+         const SHAPE_VERTEX_STRIDE: u32 = {}u;\n\
+         const TRANSLUCENT_VERTEX_STRIDE: u32 = {}u;\n",
+        CELL_LENGTH,
+        MAX_LOADED_CELLS,
+        6 * MAX_CELL_FACES,
+        6 * MAX_CELL_SHAPE_QUADS,
+        6 * MAX_TRANSLUCENT_CELL_FACES,
+    )
+}
+
+/// The size of a draw command in `Renderer::indirect_buffer`.
+const DRAW_INDIRECT_SIZE: u64 = core::mem::size_of::<wgpu::util::DrawIndirect>() as u64;
+/// The size of `Renderer::face_buffer`, the largest buffer of the renderer, which the device limits are
+/// requested for.
+pub(crate) const FACE_BUFFER_SIZE: u64 = CELL_BUFFER_SIZE * MAX_LOADED_CELLS as u64;
+
+/// The vertex counts of a slot in `Renderer::vertex_count_buffer`, of the opaque faces, the shape quads
+/// and the translucent faces.
+const SLOT_VERTEX_COUNTS_SIZE: usize = core::mem::size_of::<[u32; 3]>();
+/// The place of a slot without translucent faces in `Renderer::translucent_order_buffer`.
+const NO_TRANSLUCENT_ORDER: u32 = u32::MAX;
+
+/// Loads the shader `file_name`, prepended with the `shader_constants`.
+fn load_shader(file_name: &str) -> wgpu::ShaderSource<'static> {
+    let LoadedResource::SHADER(wgpu::ShaderSource::Wgsl(source)) = ResourceId(Some(String::from("shader")), file_name.to_string()).load(ResourceLoader::SHADER) else { unreachable!() };
+    wgpu::ShaderSource::Wgsl(Cow::Owned(shader_constants() + &source))
+}
+
 pub struct RendererState {
-    pub surface: (wgpu::Surface, wgpu::SurfaceConfiguration),
+    /// The surface is `None` for headless renderers, the configuration then describes the offscreen target.
+    pub surface: (Option<wgpu::Surface>, wgpu::SurfaceConfiguration),
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
 }
 
+/// The translucent faces of a cell and the camera position they were last uploaded sorted for, `None`
+/// until they were first uploaded.
+struct TranslucentFaces {
+    faces: Vec<PackedFace>,
+    sorted_for: InteriorCell<Option<Vec3>>,
+}
+
 pub struct Renderer {
     pub state: RendererState,
     pub camera: Camera,
+    /// The mesher used for cells meshed from now on, cells that were already meshed keep their mesh.
+    pub mesher: Box<dyn Mesher>,
+    /// The boxes outlined on top of the cells each frame.
+    pub outline_boxes: Vec<OutlineBox>,
+    pub render_mode: RenderMode,
     count_buffer: wgpu::Buffer,
+    stats_readback: StatsReadback,
+    /// The draws `command_gen.wgsl` emits for the visible cells, the opaque draws followed by the shape
+    /// draws and the translucent draws, each list with room for every slot.
     indirect_buffer: wgpu::Buffer,
-    vertex_count_buffer: BlockBuffer<4, WORLD_SIZE>,
+    vertex_count_buffer: BlockBuffer<SLOT_VERTEX_COUNTS_SIZE, MAX_LOADED_CELLS>,
     command_gen_bind_group: wgpu::BindGroup,
-    face_buffer: BlockBuffer<{ CELL_BUFFER_SIZE as usize }, WORLD_SIZE>,
+    face_buffer: BlockBuffer<{ CELL_BUFFER_SIZE as usize }, MAX_LOADED_CELLS>,
+    translucent_face_buffer: BlockBuffer<{ TRANSLUCENT_CELL_BUFFER_SIZE as usize }, MAX_LOADED_CELLS>,
+    /// The translucent faces of each cell, which are sorted by their distance to the camera.
+    translucent_faces: HashMap<IVec3, TranslucentFaces>,
+    /// The place of each slot's translucent draw in the back to front order of the cells.
+    translucent_order_buffer: wgpu::Buffer,
+    shape_buffer: BlockBuffer<{ SHAPE_CELL_BUFFER_SIZE as usize }, MAX_LOADED_CELLS>,
+    cell_pos_buffer: BindableBuffer,
+    /// The block of `face_buffer` and `vertex_count_buffer` each loaded cell is stored in.
+    cell_slots: HashMap<IVec3, usize>,
+    free_slots: Vec<usize>,
+    /// The amount of faces and shape quads meshed for the cell in each slot.
+    slot_face_counts: Vec<u32>,
     camera_buffer: BindableBuffer,
     tex_bind_group: wgpu::BindGroup,
-    cell_pipeline: wgpu::RenderPipeline,
+    /// The variant of the cell pipeline for every `RenderMode`, indexed by the mode.
+    cell_pipelines: [wgpu::RenderPipeline; RenderMode::VALUES.len()],
+    /// The variants of the cell pipeline blending the translucent faces, without depth writes.
+    translucent_cell_pipelines: [wgpu::RenderPipeline; RenderMode::VALUES.len()],
+    /// The variants of the cell pipeline drawing the quads of non-cube voxels.
+    shape_pipelines: [wgpu::RenderPipeline; RenderMode::VALUES.len()],
     command_gen_pipeline: wgpu::ComputePipeline,
+    outline_pipeline: OutlinePipeline,
     depth_texture: wgpu::TextureView,
 }
 
-struct MeshingRun {
-    pub tex: TextureId,
-    pub width: u8,
-    pub height: u8,
-}
-
 impl Renderer {
     pub fn new(state: RendererState) -> Self {
-        let face_bind_group_layout =
-            state
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                CELL_BUFFER_SIZE * WORLD_SIZE as u64,
-                            ),
-                        },
-                        count: None,
-                    }],
-                });
+        let max_buffer_size = state.device.limits().max_buffer_size;
+        assert!(
+            FACE_BUFFER_SIZE <= max_buffer_size,
+            "The face buffer takes {FACE_BUFFER_SIZE} bytes, but the device only allows buffers of {max_buffer_size} bytes!"
+        );
+        // The opaque and the translucent faces and the shape quads are bound with the same layout, so they
+        // share the cell pipeline layout.
+        let face_bind_group_layout_descriptor = wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        };
+        let face_bind_group_layout = state.device.create_bind_group_layout(&face_bind_group_layout_descriptor);
 
         let texture_bind_group_layout =
             state
@@ -114,6 +191,25 @@ impl Renderer {
                     }],
                 });
 
+        let cell_pos_bind_group_layout =
+            state
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                (CELL_POS_STRIDE * MAX_LOADED_CELLS) as u64,
+                            ),
+                        },
+                        count: None,
+                    }],
+                });
+
         let cell_pipeline_layout =
             state
                 .device
@@ -123,52 +219,57 @@ impl Renderer {
                         &face_bind_group_layout,
                         &camera_bind_group_layout,
                         &texture_bind_group_layout,
+                        &cell_pos_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
 
-        let LoadedResource::SHADER(cell_shader_source) = ResourceId(Some(String::from("shader")), String::from("cell.wgsl")).load(ResourceLoader::SHADER) else { unreachable!() };
-        let cell_pipeline = state
+        let cell_shader = state
             .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
                 label: None,
-                layout: Some(&cell_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &state
-                        .device
-                        .create_shader_module(wgpu::ShaderModuleDescriptor {
-                            label: None,
-                            source: cell_shader_source.clone(),
-                        }),
-                    entry_point: "vert",
-                    buffers: &[],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &state
-                        .device
-                        .create_shader_module(wgpu::ShaderModuleDescriptor {
-                            label: None,
-                            source: cell_shader_source,
-                        }),
-                    entry_point: "frag",
-                    targets: &[Some(state.surface.1.view_formats[0].into())],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    cull_mode: Some(wgpu::Face::Back),
-                    ..Default::default()
-                },
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: wgpu::TextureFormat::Depth32Float,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState::default(),
-                multiview: None,
+                source: load_shader("cell.wgsl"),
             });
+        let create_cell_pipeline = |render_mode: RenderMode, vertex_entry_point: &str, translucent: bool| {
+            state
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: None,
+                    layout: Some(&cell_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &cell_shader,
+                        entry_point: vertex_entry_point,
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &cell_shader,
+                        entry_point: render_mode.fragment_entry_point(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: state.surface.1.view_formats[0],
+                            blend: translucent.then_some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        cull_mode: Some(wgpu::Face::Back),
+                        polygon_mode: render_mode.polygon_mode(),
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: wgpu::TextureFormat::Depth32Float,
+                        depth_write_enabled: !translucent,
+                        depth_compare: wgpu::CompareFunction::Less,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+        };
+        let cell_pipelines = RenderMode::VALUES.map(|render_mode| create_cell_pipeline(render_mode, "vert", false));
+        let translucent_cell_pipelines = RenderMode::VALUES.map(|render_mode| create_cell_pipeline(render_mode, "vert", true));
+        let shape_pipelines = RenderMode::VALUES.map(|render_mode| create_cell_pipeline(render_mode, "vert_shape", false));
 
-        let LoadedResource::SHADER(command_gen_shader_source) = ResourceId(Some(String::from("shader")), String::from("command_gen.wgsl")).load(ResourceLoader::SHADER) else { unreachable!() };
         let command_gen_pipeline =
             state
                 .device
@@ -179,23 +280,30 @@ impl Renderer {
                         .device
                         .create_shader_module(wgpu::ShaderModuleDescriptor {
                             label: None,
-                            source: command_gen_shader_source,
+                            source: load_shader("command_gen.wgsl"),
                         }),
                     entry_point: "main",
                 });
 
+        let outline_pipeline = OutlinePipeline::new(&state, &camera_bind_group_layout);
+
         let depth_texture = Self::create_depth_texture(&state);
 
         let count_buffer = state.device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: core::mem::size_of::<u32>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            size: DRAW_COUNTS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
+        let stats_readback = StatsReadback::new(&state.device);
+
         let indirect_buffer = state.device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
-            size: (core::mem::size_of::<wgpu::util::DrawIndirect>() * WORLD_SIZE) as u64,
+            size: DRAW_INDIRECT_SIZE * 3 * MAX_LOADED_CELLS as u64,
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
             mapped_at_creation: false,
         });
@@ -205,43 +313,56 @@ impl Renderer {
             None,
             &wgpu::BufferDescriptor {
                 label: None,
-                size: (core::mem::size_of::<u32>() * WORLD_SIZE) as u64,
+                size: (SLOT_VERTEX_COUNTS_SIZE * MAX_LOADED_CELLS) as u64,
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             },
         );
 
-        let command_gen_bind_group = state.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &command_gen_pipeline.get_bind_group_layout(0),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: count_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: indirect_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: vertex_count_buffer.as_entire_binding(),
-                },
-            ],
+        let translucent_order_buffer = state.device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
+            size: (core::mem::size_of::<u32>() * MAX_LOADED_CELLS) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
+        state
+            .queue
+            .write_buffer(&translucent_order_buffer, 0, bytemuck::cast_slice(&[NO_TRANSLUCENT_ORDER; MAX_LOADED_CELLS]));
 
         let face_buffer = BlockBuffer::new(
             &state.device,
             Some(face_bind_group_layout),
             &wgpu::BufferDescriptor {
                 label: None,
-                size: CELL_BUFFER_SIZE * WORLD_SIZE as u64,
+                size: FACE_BUFFER_SIZE,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let translucent_face_buffer = BlockBuffer::new(
+            &state.device,
+            Some(state.device.create_bind_group_layout(&face_bind_group_layout_descriptor)),
+            &wgpu::BufferDescriptor {
+                label: None,
+                size: TRANSLUCENT_CELL_BUFFER_SIZE * MAX_LOADED_CELLS as u64,
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             },
         );
 
-        let block_tex_array_view = create_texture_array(&state.device, &state.queue, 16, 16)
+        let shape_buffer = BlockBuffer::new(
+            &state.device,
+            Some(state.device.create_bind_group_layout(&face_bind_group_layout_descriptor)),
+            &wgpu::BufferDescriptor {
+                label: None,
+                size: SHAPE_CELL_BUFFER_SIZE * MAX_LOADED_CELLS as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        );
+
+        let block_tex_array_view = create_texture_array(&state.device, &state.queue)
             .create_view(&wgpu::TextureViewDescriptor {
                 dimension: Some(wgpu::TextureViewDimension::D2Array),
                 ..Default::default()
@@ -273,6 +394,17 @@ impl Renderer {
             label: None,
         });
 
+        let cell_pos_buffer = BindableBuffer::new(
+            &state.device,
+            &cell_pos_bind_group_layout,
+            state.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: (CELL_POS_STRIDE * MAX_LOADED_CELLS) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        );
+
         let camera = Camera::new(state.surface.1.width as f32 / state.surface.1.height as f32);
         let camera_buffer = BindableBuffer::new(
             &state.device,
@@ -285,18 +417,64 @@ impl Renderer {
             }),
         );
 
+        let command_gen_bind_group = state.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &command_gen_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: count_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: vertex_count_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: cell_pos_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: translucent_order_buffer.as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
         Self {
             state,
             camera,
+            mesher: Box::new(GreedyMesher),
+            outline_boxes: Vec::new(),
             count_buffer,
+            stats_readback,
             indirect_buffer,
             vertex_count_buffer,
             command_gen_bind_group,
             face_buffer,
+            translucent_face_buffer,
+            translucent_faces: HashMap::new(),
+            translucent_order_buffer,
+            shape_buffer,
+            cell_pos_buffer,
+            cell_slots: HashMap::new(),
+            free_slots: (0..MAX_LOADED_CELLS).rev().collect(),
+            slot_face_counts: vec![0; MAX_LOADED_CELLS],
             camera_buffer,
             tex_bind_group,
-            cell_pipeline,
+            cell_pipelines,
+            translucent_cell_pipelines,
+            shape_pipelines,
+            render_mode: RenderMode::default(),
             command_gen_pipeline,
+            outline_pipeline,
             depth_texture,
         }
     }
@@ -322,140 +500,127 @@ impl Renderer {
     }
 
     pub fn mesh_cell(&mut self, cell: &Cell, world: &World) {
-        let mut faces: [[u32; CELL_SIZE]; 6] = [[0; CELL_SIZE]; 6];
-
-        for z in 0..32 { for y in 0..32 { for x in 0..32 {
-            let idx = Cell::encode(x, y, z);
-            let voxel_id = unsafe { *cell.voxels.get_unchecked(idx) };
-            let model = unsafe { VOXEL_REGISTRY.get(voxel_id).model };
-            if model.is_none() {
-                continue;
-            }
-
-            const NEIGHBOR_MASKS: usizex4 = usizex4::from_array([CELL_Z_MASK, CELL_X_MASK, CELL_Y_MASK, 0]);
-            const NEIGHBOR_DECODING_MASKS: usizex4 = usizex4::from_array([31, 31, 31, 0]);
-            const NEIGHBOR_DECODING: usizex4 = usizex4::from_array([0, 10, 5, 0]);
-            let idx_splat = usizex4::splat(idx);
-            let invalid_negative_neighbors = ((idx_splat >> NEIGHBOR_DECODING) & NEIGHBOR_DECODING_MASKS).simd_eq(usizex4::splat(0));
-            let invalid_positive_neighbors = ((idx_splat >> NEIGHBOR_DECODING) & NEIGHBOR_DECODING_MASKS).simd_eq(usizex4::splat(31));
-
-            let invalid_neighbors = simd_swizzle!(invalid_negative_neighbors.to_int(), invalid_positive_neighbors.to_int(), [
-                First(0),
-                First(1),
-                First(2),
-                Second(0),
-                Second(1),
-                Second(2),
-                Second(3),
-                Second(3),
-            ]);
-
-            let neighbor_indices = simd_swizzle!(idx_splat - NEIGHBOR_MASKS, idx_splat + NEIGHBOR_MASKS, [
-                First(0),
-                First(1),
-                First(2),
-                Second(0),
-                Second(1),
-                Second(2),
-                Second(3),
-                Second(3),
-            ]);
-
-            for i in 0..6usize {
-                let face = unsafe { *model.unwrap().0.get_unchecked(i) };
-                let neighbor_index = neighbor_indices[i];
-
-                if invalid_neighbors[i] == 0 {
-                    if unsafe { VOXEL_REGISTRY.get(*cell.voxels.get_unchecked(neighbor_index)) }.model.is_some() {
-                        continue;
-                    }
-                }
-
-                unsafe { *faces.get_unchecked_mut(i).get_unchecked_mut(idx) = (0 << 0) | (0 << 5) | ((face.tex_id as u32) << 10)};
-            }
-        }}}
-
-        let mut mesh: Vec<u32> = Vec::new();
-        for i in 0..6usize {
-            let faces = unsafe { faces.get_unchecked(i) };
-
-            const CHUNK_SIZE: usize = 16;
-            let mut chunk_index = 0;
-            for chunk in faces.array_chunks::<CHUNK_SIZE>() {
-                let arr = u32x16::from_array(*chunk);
-                let mut chunk_mesh = u32x16::from_array([
-                    chunk_index + 0,
-                    chunk_index + 1,
-                    chunk_index + 2,
-                    chunk_index + 3,
-                    chunk_index + 4,
-                    chunk_index + 5,
-                    chunk_index + 6,
-                    chunk_index + 7,
-                    chunk_index + 8,
-                    chunk_index + 9,
-                    chunk_index + 10,
-                    chunk_index + 11,
-                    chunk_index + 12,
-                    chunk_index + 13,
-                    chunk_index + 14,
-                    chunk_index + 15,
-                ]);
-
-                chunk_mesh |= u32x16::splat((i as u32) << 15);
-                chunk_mesh |= ((arr >> u32x16::splat(10)) & u32x16::splat(7)) << u32x16::splat(18);
-                chunk_mesh |= ((arr >> u32x16::splat(0)) & u32x16::splat(31)) << u32x16::splat(21);
-                chunk_mesh |= ((arr >> u32x16::splat(5)) & u32x16::splat(31)) << u32x16::splat(26);
-
-                for j in 0..16usize {
-                    if chunk[j] != 0 { mesh.push(chunk_mesh[j]); }
-                }
-
-                chunk_index += CHUNK_SIZE as u32;
-            }
+        let mut mesh = self.mesher.mesh(cell, &world.neighbor_cells(cell.pos));
+        // Opaque faces never exceed the limit on their own, so only cutout faces are left out.
+        let dropped = mesh.truncate_opaque(MAX_CELL_FACES);
+        if dropped > 0 {
+            log::warn!(
+                "The cell at {:?} meshed to {} faces, {dropped} cutout faces past {MAX_CELL_FACES} are left out.",
+                cell.pos,
+                MAX_CELL_FACES + dropped
+            );
         }
 
-        let idx = World::encode(cell.pos.x, cell.pos.y, cell.pos.z);
+        let idx = self.cell_slot(cell.pos);
         self.face_buffer.write_to_block(
             &self.state.queue,
             idx,
-            bytemuck::cast_slice(&mesh.as_slice()),
+            bytemuck::cast_slice(mesh.opaque.as_slice()),
         );
+        self.slot_face_counts[idx] = mesh.len() as u32;
+
+        // Quads past the capacity of a slot are left out.
+        let shapes = &mesh.shapes[..mesh.shapes.len().min(MAX_CELL_SHAPE_QUADS)];
+        self.shape_buffer
+            .write_to_block(&self.state.queue, idx, bytemuck::cast_slice(shapes));
+        // The translucent faces are uploaded once they're sorted, the farthest past the capacity of a slot are left out.
+        let translucent_face_count = mesh.translucent.len().min(MAX_TRANSLUCENT_CELL_FACES);
         self.vertex_count_buffer.write_to_block(
             &self.state.queue,
             idx,
-            &(6 * mesh.len() as u32).to_le_bytes(),
+            bytemuck::cast_slice(&[6 * mesh.opaque.len() as u32, 6 * shapes.len() as u32, 6 * translucent_face_count as u32]),
+        );
+
+        if mesh.translucent.len() > MAX_TRANSLUCENT_CELL_FACES {
+            log::warn!(
+                "The cell at {:?} meshed to {} translucent faces, the farthest past {MAX_TRANSLUCENT_CELL_FACES} are left out.",
+                cell.pos,
+                mesh.translucent.len()
+            );
+        }
+        if mesh.translucent.is_empty() {
+            self.translucent_faces.remove(&cell.pos);
+            self.set_translucent_order(idx, NO_TRANSLUCENT_ORDER);
+        } else {
+            self.translucent_faces.insert(cell.pos, TranslucentFaces {
+                faces: mesh.translucent,
+                sorted_for: InteriorCell::new(None),
+            });
+        }
+    }
+
+    /// The amount of faces the cell at `pos` was last meshed with, `None` if it was never meshed.
+    pub fn meshed_face_count(&self, pos: IVec3) -> Option<u32> {
+        self.cell_slots.get(&pos).map(|slot| self.slot_face_counts[*slot])
+    }
+
+    /// The slot of the cell at `pos`, allocating one if the cell doesn't have one yet.
+    fn cell_slot(&mut self, pos: IVec3) -> usize {
+        if let Some(&slot) = self.cell_slots.get(&pos) {
+            return slot;
+        }
+
+        let slot = self.free_slots.pop().expect("Ran out of cell slots!");
+        self.cell_slots.insert(pos, slot);
+        self.state.queue.write_buffer(
+            &self.cell_pos_buffer.buffer,
+            (CELL_POS_STRIDE * slot) as u64,
+            bytemuck::cast_slice(&[pos.x, pos.y, pos.z, 0]),
         );
+        slot
+    }
+
+    /// Stops drawing the cell at `pos` and frees its slot for another cell.
+    pub fn unload_cell(&mut self, pos: IVec3) {
+        let Some(slot) = self.cell_slots.remove(&pos) else { return };
+        self.translucent_faces.remove(&pos);
+        self.vertex_count_buffer
+            .write_to_block(&self.state.queue, slot, &[0; SLOT_VERTEX_COUNTS_SIZE]);
+        self.set_translucent_order(slot, NO_TRANSLUCENT_ORDER);
+        self.free_slots.push(slot);
     }
 
-    pub fn render(&self, frame: &wgpu::SurfaceTexture) {
+    /// Remeshes and re-uploads every cell that was modified since it was last meshed.
+    pub fn remesh_dirty_cells(&mut self, world: &mut World) {
+        let dirty: Vec<IVec3> = world
+            .cells
+            .values()
+            .filter(|cell| cell.dirty)
+            .map(|cell| cell.pos)
+            .collect();
+        for pos in dirty {
+            self.mesh_cell(&world.cells[&pos], world);
+            world.cells.get_mut(&pos).unwrap().dirty = false;
+        }
+    }
+
+    pub fn render(&self, view: &wgpu::TextureView) {
         self.state.queue.write_buffer(
             &self.camera_buffer.buffer,
             0,
             bytemuck::cast_slice(&[self.camera.matrices()]),
         );
-
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.sort_translucent_faces();
 
         let mut encoder = self
             .state
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        // The compute pass appends the draws of the visible cells, starting from an empty list every frame.
+        encoder.clear_buffer(&self.count_buffer, 0, None);
         {
             let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
 
             pass.set_pipeline(&self.command_gen_pipeline);
             pass.set_bind_group(0, &self.command_gen_bind_group, &[]);
-            pass.dispatch_workgroups(WORLD_LENGTH as u32, WORLD_LENGTH as u32, WORLD_LENGTH as u32);
+            pass.dispatch_workgroups(MAX_LOADED_CELLS as u32 / 64, 1, 1);
         }
 
         {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -472,20 +637,166 @@ impl Renderer {
                 }),
             });
 
-            pass.set_pipeline(&self.cell_pipeline);
+            pass.set_pipeline(&self.cell_pipelines[self.render_mode as usize]);
             self.camera_buffer.bind(1, &mut pass);
             self.face_buffer.bind(0, &mut pass);
             pass.set_bind_group(2, &self.tex_bind_group, &[]);
+            self.cell_pos_buffer.bind(3, &mut pass);
             pass.multi_draw_indirect_count(
                 &self.indirect_buffer,
                 0,
                 &self.count_buffer,
                 0,
-                WORLD_SIZE as u32,
+                MAX_LOADED_CELLS as u32,
             );
+
+            pass.set_pipeline(&self.shape_pipelines[self.render_mode as usize]);
+            self.shape_buffer.bind(0, &mut pass);
+            pass.multi_draw_indirect_count(
+                &self.indirect_buffer,
+                DRAW_INDIRECT_SIZE * MAX_LOADED_CELLS as u64,
+                &self.count_buffer,
+                SHAPE_DRAW_COUNT_OFFSET,
+                MAX_LOADED_CELLS as u32,
+            );
+
+            if !self.translucent_faces.is_empty() {
+                pass.set_pipeline(&self.translucent_cell_pipelines[self.render_mode as usize]);
+                self.translucent_face_buffer.bind(0, &mut pass);
+                pass.multi_draw_indirect_count(
+                    &self.indirect_buffer,
+                    DRAW_INDIRECT_SIZE * 2 * MAX_LOADED_CELLS as u64,
+                    &self.count_buffer,
+                    TRANSLUCENT_DRAW_COUNT_OFFSET,
+                    MAX_LOADED_CELLS as u32,
+                );
+            }
+
+            self.outline_pipeline
+                .draw(&self.state.queue, &mut pass, &self.camera_buffer, &self.outline_boxes);
+        }
+
+        let copied_stats = self.stats_readback.copy(&mut encoder, &self.count_buffer);
+        self.state.queue.submit(Some(encoder.finish()));
+        if copied_stats {
+            self.stats_readback.map();
+        }
+    }
+
+    /// Uploads the translucent faces of every cell sorted back to front, and the back to front order of
+    /// the cells `command_gen.wgsl` places their draws in. Cells are only sorted again once the camera
+    /// moved or they were remeshed.
+    fn sort_translucent_faces(&self) {
+        let mut order = Vec::new();
+        for (pos, translucent) in &self.translucent_faces {
+            let Some(&slot) = self.cell_slots.get(pos) else { continue };
+            let origin = *pos * CELL_LENGTH as i32;
+            let origin = Vec3::new(origin.x as f32, origin.y as f32, origin.z as f32);
+            let distance = |center: [f32; 3]| (origin + Vec3::from(center) - self.camera.pos).mag_sq();
+
+            if translucent.sorted_for.get() != Some(self.camera.pos) {
+                let face_count = translucent.faces.len().min(MAX_TRANSLUCENT_CELL_FACES);
+                let mut sorted: Vec<(f32, PackedFace)> = translucent.faces.iter().map(|face| (distance(face_center(*face)), *face)).collect();
+                sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+                let sorted: Vec<PackedFace> = sorted[sorted.len() - face_count..].iter().map(|(_, face)| *face).collect();
+                self.translucent_face_buffer
+                    .write_to_block(&self.state.queue, slot, bytemuck::cast_slice(&sorted));
+                translucent.sorted_for.set(Some(self.camera.pos));
+            }
+
+            let half_cell = CELL_LENGTH as f32 / 2.0;
+            order.push((distance([half_cell; 3]), slot));
         }
+        order.sort_by(|a, b| b.0.total_cmp(&a.0));
+        for (place, (_, slot)) in order.into_iter().enumerate() {
+            self.set_translucent_order(slot, place as u32);
+        }
+    }
+
+    fn set_translucent_order(&self, slot: usize, place: u32) {
+        self.state.queue.write_buffer(
+            &self.translucent_order_buffer,
+            (core::mem::size_of::<u32>() * slot) as u64,
+            &place.to_le_bytes(),
+        );
+    }
 
+    /// The draw statistics of a recent frame, read back from the GPU without waiting for it.
+    /// Returns `None` until the first readback finished.
+    pub fn stats(&self) -> Option<RenderStats> {
+        self.stats_readback.latest(&self.state.device)
+    }
+
+    /// Renders a frame into a new offscreen texture and reads it back, blocking until the GPU finished.
+    /// Fails if the texture couldn't be read back, like when the device was lost.
+    pub fn render_to_image(&self) -> Result<image::RgbaImage, wgpu::BufferAsyncError> {
+        let surface_configuration = &self.state.surface.1;
+        let size = wgpu::Extent3d {
+            width: surface_configuration.width,
+            height: surface_configuration.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.state.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: surface_configuration.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        self.render(&texture.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        // Rows of texture copies have to be aligned, the padding is stripped again when reading.
+        let row_size = 4 * size.width;
+        let padded_row_size = (row_size + wgpu::COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            / wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.state.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row_size * size.height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .state
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
         self.state.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.state.device.poll(wgpu::Maintain::Wait);
+        // The callback has run once the device finished, a dropped sender means the mapping was cancelled.
+        receiver.recv().unwrap_or(Err(wgpu::BufferAsyncError))?;
+
+        let mut pixels = Vec::with_capacity((row_size * size.height) as usize);
+        for row in slice.get_mapped_range().chunks_exact(padded_row_size as usize) {
+            pixels.extend_from_slice(&row[..row_size as usize]);
+        }
+        if surface_configuration.format == wgpu::TextureFormat::Bgra8Unorm {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(image::RgbaImage::from_raw(size.width, size.height, pixels).unwrap())
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
@@ -498,11 +809,27 @@ impl Renderer {
         self.camera.aspect =
             surface_configuration.width as f32 / surface_configuration.height as f32;
 
-        self.state
-            .surface
-            .0
-            .configure(&self.state.device, &surface_configuration);
+        if let Some(surface) = &self.state.surface.0 {
+            surface.configure(&self.state.device, &surface_configuration);
+        }
 
         self.depth_texture = Self::create_depth_texture(&self.state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shaders_are_valid_with_the_shared_constants() {
+        for file_name in ["cell.wgsl", "command_gen.wgsl"] {
+            let path = format!("{}/../raxel-demo/src/resources/shader/{file_name}", env!("CARGO_MANIFEST_DIR"));
+            let source = shader_constants() + &std::fs::read_to_string(path).unwrap();
+            let module = naga::front::wgsl::parse_str(&source).unwrap_or_else(|err| panic!("{}", err.emit_to_string(&source)));
+            naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+                .validate(&module)
+                .unwrap_or_else(|err| panic!("{file_name} is invalid: {err:?}"));
+        }
+    }
+}
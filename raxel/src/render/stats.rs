@@ -0,0 +1,99 @@
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// The size of the draw counts written by `command_gen.wgsl`: the opaque draw count, the face count of
+/// the opaque and translucent draws, the shape and the translucent draw count, the visible cell count
+/// and the shape quad count.
+pub const DRAW_COUNTS_SIZE: u64 = (core::mem::size_of::<u32>() * 6) as u64;
+/// The offsets of the shape and the translucent draw counts in the draw counts.
+pub const SHAPE_DRAW_COUNT_OFFSET: u64 = (core::mem::size_of::<u32>() * 2) as u64;
+pub const TRANSLUCENT_DRAW_COUNT_OFFSET: u64 = (core::mem::size_of::<u32>() * 3) as u64;
+
+/// What a frame drew, over all three passes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    /// The cells inside the view frustum with anything to draw.
+    pub visible_cells: u32,
+    /// The opaque, cutout and translucent cube faces drawn.
+    pub face_count: u32,
+    /// The quads of the voxels that aren't cubes drawn.
+    pub shape_quad_count: u32,
+}
+
+/// Copies the draw counts of a frame to a mappable buffer and reads them back without stalling,
+/// a new copy is only started once the previous one was read.
+pub struct StatsReadback {
+    buffer: wgpu::Buffer,
+    pending: Cell<bool>,
+    mapped: Arc<AtomicBool>,
+    /// Set when the pending mapping failed, so a new copy can be started.
+    failed: Arc<AtomicBool>,
+    latest: Cell<Option<RenderStats>>,
+}
+
+impl StatsReadback {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: DRAW_COUNTS_SIZE,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            pending: Cell::new(false),
+            mapped: Arc::new(AtomicBool::new(false)),
+            failed: Arc::new(AtomicBool::new(false)),
+            latest: Cell::new(None),
+        }
+    }
+
+    /// Records a copy of `counts_buffer` unless a readback is still in flight, returns whether it did.
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, counts_buffer: &wgpu::Buffer) -> bool {
+        if self.pending.get() {
+            return false;
+        }
+        encoder.copy_buffer_to_buffer(counts_buffer, 0, &self.buffer, 0, DRAW_COUNTS_SIZE);
+        true
+    }
+
+    /// Starts mapping the buffer, must be called after the commands recorded by `copy` were submitted.
+    pub fn map(&self) {
+        self.pending.set(true);
+        let (mapped, failed) = (self.mapped.clone(), self.failed.clone());
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(_) => failed.store(true, Ordering::Release),
+            });
+    }
+
+    /// The most recently read stats, reads the pending copy first if it was mapped in the meantime.
+    /// A copy whose mapping failed is dropped and the next frame starts a new one.
+    pub fn latest(&self, device: &wgpu::Device) -> Option<RenderStats> {
+        device.poll(wgpu::Maintain::Poll);
+        // A failed mapping leaves the buffer unmapped, unmapping it again would be a validation error.
+        if self.failed.swap(false, Ordering::Acquire) {
+            self.pending.set(false);
+        }
+        if self.mapped.swap(false, Ordering::Acquire) {
+            {
+                let data = self.buffer.slice(..).get_mapped_range();
+                let counts: &[u32] = bytemuck::cast_slice(&data);
+                self.latest.set(Some(RenderStats {
+                    visible_cells: counts[4],
+                    face_count: counts[1],
+                    shape_quad_count: counts[5],
+                }));
+            }
+            self.buffer.unmap();
+            self.pending.set(false);
+        }
+        self.latest.get()
+    }
+}
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, fs};
+use std::{borrow::Cow, fs, path::PathBuf};
 
 pub enum ResourceLoader {
     TEXTURE,
@@ -8,7 +8,8 @@ pub enum ResourceLoader {
 pub struct ResourceId(pub Option<String>, pub String);
 
 impl ResourceId {
-    pub fn load<'a>(&self, loader: ResourceLoader) -> LoadedResource<'a> {
+    /// The path of the resource in the `resources` folder next to the executable.
+    pub fn path(&self) -> PathBuf {
         let formatted = &format!(
             "resources/{}{}",
             self.0
@@ -17,11 +18,15 @@ impl ResourceId {
                 .unwrap_or(String::new()),
             self.1
         );
-        let path = &std::env::current_exe()
+        std::env::current_exe()
             .unwrap()
             .parent()
             .unwrap()
-            .join(formatted);
+            .join(formatted)
+    }
+
+    pub fn load<'a>(&self, loader: ResourceLoader) -> LoadedResource<'a> {
+        let path = &self.path();
         match loader {
             ResourceLoader::SHADER => {
                 let data = fs::read_to_string(path)
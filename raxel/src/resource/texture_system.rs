@@ -1,28 +1,86 @@
-use std::{num::NonZeroU32, sync::Mutex};
+use std::{collections::HashMap, num::NonZeroU32, sync::Mutex};
 
 use once_cell::sync::Lazy;
 
 use super::resource::LoadedResource;
 
-pub type TextureId = u8;
+pub type TextureId = u16;
+
+/// The most textures that can be added, as `visible_faces` holds the `TextureId` of a face plus one in 12 bits.
+pub const MAX_TEXTURES: usize = (1 << 12) - 1;
 
 static TEXTURE_SET: Lazy<Mutex<Vec<image::DynamicImage>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// The names block model files refer to textures by.
+static TEXTURE_NAMES: Lazy<Mutex<HashMap<String, TextureId>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
+/// Adds a texture to the texture array. The first texture sets the resolution of all textures, later
+/// textures of another size are scaled to it.
 pub fn add_texture(texture_resource: LoadedResource) -> Result<TextureId, &'static str> {
     let LoadedResource::TEXTURE(image) = texture_resource else { return Err("A texture resource wasn't supplied to add_texture!") };
+    if image.width() == 0 || image.height() == 0 {
+        return Err("An empty texture was supplied to add_texture!");
+    }
     let mut texture_set = TEXTURE_SET.lock().unwrap();
+    if texture_set.len() == MAX_TEXTURES {
+        return Err("No more than MAX_TEXTURES textures can be added!");
+    }
+    let image = match texture_set.first() {
+        Some(first) if (first.width(), first.height()) != (image.width(), image.height()) => {
+            image.resize_exact(first.width(), first.height(), image::imageops::FilterType::Nearest)
+        }
+        _ => image,
+    };
     texture_set.push(image.flipv());
     Ok((texture_set.len() - 1) as TextureId)
 }
 
-pub fn create_texture_array(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    texture_width: u32,
-    texture_height: u32,
-) -> wgpu::Texture {
+/// The width and height of every texture, `None` if no texture was added yet.
+pub fn texture_resolution() -> Option<(u32, u32)> {
+    let texture_set = TEXTURE_SET.lock().unwrap();
+    texture_set.first().map(|image| (image.width(), image.height()))
+}
+
+/// Adds a texture that block model files can refer to by `name`.
+pub fn add_named_texture(name: &str, texture_resource: LoadedResource) -> Result<TextureId, &'static str> {
+    let tex_id = add_texture(texture_resource)?;
+    TEXTURE_NAMES.lock().unwrap().insert(name.to_string(), tex_id);
+    Ok(tex_id)
+}
+
+/// The id of the texture added with `name`, see `add_named_texture`.
+pub fn texture_id(name: &str) -> Option<TextureId> {
+    TEXTURE_NAMES.lock().unwrap().get(name).copied()
+}
+
+/// The average colour of an added texture, `None` if no texture with the id was added.
+pub fn average_color(tex_id: TextureId) -> Option<[u8; 4]> {
+    let texture_set = TEXTURE_SET.lock().unwrap();
+    let image = texture_set.get(tex_id as usize)?.to_rgba8();
+    let mut sum = [0u64; 4];
+    for pixel in image.pixels() {
+        for (channel, value) in sum.iter_mut().zip(pixel.0) {
+            *channel += value as u64;
+        }
+    }
+    let pixel_count = (image.width() as u64 * image.height() as u64).max(1);
+    Some(sum.map(|channel| (channel / pixel_count) as u8))
+}
+
+/// A copy of an added texture the right way up, `None` if no texture with the id was added.
+pub fn texture_image(tex_id: TextureId) -> Option<image::RgbaImage> {
+    let texture_set = TEXTURE_SET.lock().unwrap();
+    Some(texture_set.get(tex_id as usize)?.flipv().to_rgba8())
+}
+
+/// Uploads the added textures into a texture array of their resolution, see `texture_resolution`.
+pub fn create_texture_array(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+    let (texture_width, texture_height) = texture_resolution().expect("No textures were added!");
     let texture_set = TEXTURE_SET.lock().unwrap();
     let texture_count = texture_set.len() as u32;
+    assert!(
+        texture_count <= device.limits().max_texture_array_layers,
+        "The device can't hold {texture_count} textures in a texture array!"
+    );
     let texture_extent = wgpu::Extent3d {
         width: texture_width,
         height: texture_height,
@@ -1,3 +1,4 @@
+pub mod modelfile;
 pub mod voxel;
 pub mod voxelface;
 pub mod voxelmodel;
@@ -0,0 +1,300 @@
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+
+use crate::resource::{
+    resource::ResourceId,
+    texture_system::{self, TextureId},
+};
+
+use super::{
+    voxelface::{Norm, VoxelFace},
+    voxelmodel::{ModelBox, ModelFace, VoxelModel, MODEL_UNITS},
+};
+
+/// A block model file, like
+/// ```json
+/// {
+///     "parent": "cube_all",
+///     "textures": { "all": "stone" }
+/// }
+/// ```
+/// The textures and the elements of the model are inherited from the parent model in the same folder,
+/// textures and elements of the child replace the parent's.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ModelFile {
+    pub parent: Option<String>,
+    /// Texture variables, mapping to the name of a texture (see `add_named_texture`) or another variable
+    /// written as `#variable`.
+    #[serde(default)]
+    pub textures: HashMap<String, String>,
+    pub elements: Option<Vec<ModelElement>>,
+}
+
+/// A box of a model file from `from` to `to` in sixteenths of a voxel.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ModelElement {
+    pub from: [u8; 3],
+    pub to: [u8; 3],
+    pub rotation: Option<ElementRotation>,
+    /// The faces of the box, the others are never seen.
+    #[serde(default)]
+    pub faces: HashMap<Norm, ElementFace>,
+}
+
+/// A rotation of an element around the center of the voxel, in steps of 90 degrees counterclockwise
+/// looking from the positive end of the axis. The faces keep their texture orientation.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct ElementRotation {
+    pub axis: Axis,
+    pub angle: i32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Axis {
+    X = 0,
+    Y = 1,
+    Z = 2,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElementFace {
+    /// The name of a texture or a texture variable written as `#variable`.
+    pub texture: String,
+    /// The rectangle of the texture shown on the face, see `ModelFace`. Defaults to the part a full cube
+    /// face would show at the place of the face.
+    pub uv: Option<[u8; 4]>,
+    /// The side of the voxel whose opaque neighbor hides the face.
+    pub cullface: Option<Norm>,
+}
+
+/// Loads the block model `id` along with its parents. A model of one full box whose faces are all culled
+/// on their side and show their whole texture becomes a `VoxelModel::CUBE`. Missing or invalid model files
+/// are reported as errors.
+pub fn load_model(id: &ResourceId) -> Result<VoxelModel, String> {
+    resolve_model(&id.1, |file_name| {
+        let path = ResourceId(id.0.clone(), file_name.to_string()).path();
+        let data = fs::read_to_string(&path).map_err(|err| format!("No model at {}: {err}", path.display()))?;
+        serde_json::from_str(&data).map_err(|err| format!("Invalid model at {}: {err}", path.display()))
+    })
+}
+
+/// Builds the model in the file `file_name`, reading it and its parents with `read_file`.
+fn resolve_model(file_name: &str, mut read_file: impl FnMut(&str) -> Result<ModelFile, String>) -> Result<VoxelModel, String> {
+    let mut textures = HashMap::new();
+    let mut elements = None;
+    let mut file_names = Vec::new();
+    let mut next = Some(file_name.to_string());
+    while let Some(file_name) = next {
+        if file_names.contains(&file_name) {
+            return Err(format!("The model {file_name} inherits from itself!"));
+        }
+        let model_file = read_file(&file_name)?;
+        for (variable, texture) in model_file.textures {
+            textures.entry(variable).or_insert(texture);
+        }
+        if elements.is_none() {
+            elements = model_file.elements;
+        }
+        next = model_file.parent.map(|parent| parent + ".json");
+        file_names.push(file_name);
+    }
+
+    let elements = elements.ok_or_else(|| format!("The model {file_name} has no elements!"))?;
+    let boxes = elements
+        .iter()
+        .map(|element| model_box(element, &textures))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let [model_box] = boxes.as_slice() {
+        let is_cube = model_box.min == [0; 3]
+            && model_box.max == [MODEL_UNITS; 3]
+            && Norm::VALUES.iter().zip(model_box.faces).all(|(norm, face)| {
+                face.is_some_and(|face| face.cull == Some(*norm) && face.uv == [0, 0, MODEL_UNITS, MODEL_UNITS])
+            });
+        if is_cube {
+            return Ok(VoxelModel::CUBE(Norm::VALUES.map(|norm| VoxelFace {
+                norm,
+                tex_id: model_box.faces[norm as usize].unwrap().tex_id,
+            })));
+        }
+    }
+    Ok(VoxelModel::BOXES(boxes))
+}
+
+fn model_box(element: &ModelElement, textures: &HashMap<String, String>) -> Result<ModelBox, String> {
+    let [from, to] = [element.from, element.to];
+    if !(0..3).all(|axis| from[axis] < to[axis] && to[axis] <= MODEL_UNITS) {
+        return Err(format!("The element from {from:?} to {to:?} has to lie inside the voxel and can't be empty!"));
+    }
+    let quarter_turns = match element.rotation {
+        Some(rotation) if rotation.angle % 90 != 0 => {
+            return Err(format!("The rotation angle {} isn't a multiple of 90 degrees!", rotation.angle));
+        }
+        Some(rotation) => (rotation.angle.rem_euclid(360) / 90) as usize,
+        None => 0,
+    };
+    let rotation_axis = element.rotation.map_or(Axis::Y, |rotation| rotation.axis);
+    let rotate_norm = |norm: Norm| {
+        let offset = Norm::OFFSETS[norm as usize];
+        let offset = rotate([offset.x, offset.y, offset.z], rotation_axis, quarter_turns, 0);
+        Norm::VALUES
+            .into_iter()
+            .find(|norm| {
+                let other = Norm::OFFSETS[*norm as usize];
+                [other.x, other.y, other.z] == offset
+            })
+            .unwrap()
+    };
+
+    let [from, to] = [from, to].map(|corner| rotate(corner.map(|value| value as i32), rotation_axis, quarter_turns, MODEL_UNITS as i32));
+    let min = [0, 1, 2].map(|axis| from[axis].min(to[axis]) as u8);
+    let max = [0, 1, 2].map(|axis| from[axis].max(to[axis]) as u8);
+    let mut faces = [None; 6];
+    for (norm, face) in &element.faces {
+        let norm = rotate_norm(*norm);
+        let uv = face.uv.unwrap_or_else(|| ModelBox::default_uv(min, max, norm));
+        if uv.iter().any(|value| *value > MODEL_UNITS) {
+            return Err(format!("The uv {uv:?} has to lie inside the texture!"));
+        }
+        faces[norm as usize] = Some(ModelFace {
+            tex_id: resolve_texture(&face.texture, textures)?,
+            uv,
+            cull: face.cullface.map(rotate_norm),
+        });
+    }
+    Ok(ModelBox { min, max, faces })
+}
+
+/// Turns `point` by `quarter_turns` steps of 90 degrees around the center of a cube from 0 to `extent`.
+fn rotate(mut point: [i32; 3], axis: Axis, quarter_turns: usize, extent: i32) -> [i32; 3] {
+    // The two other axes, the first one turns towards the second.
+    let [from, to] = match axis {
+        Axis::X => [1, 2],
+        Axis::Y => [2, 0],
+        Axis::Z => [0, 1],
+    };
+    for _ in 0..quarter_turns {
+        [point[from], point[to]] = [extent - point[to], point[from]];
+    }
+    point
+}
+
+/// Follows the texture variables starting at `texture` to the named texture.
+fn resolve_texture(texture: &str, textures: &HashMap<String, String>) -> Result<TextureId, String> {
+    let mut name = texture;
+    // Each step follows a different variable, unless they form a cycle.
+    for _ in 0..=textures.len() {
+        let Some(variable) = name.strip_prefix('#') else {
+            return texture_system::texture_id(name).ok_or_else(|| format!("There's no texture named {name}!"));
+        };
+        name = textures
+            .get(variable)
+            .ok_or_else(|| format!("The texture variable {variable} isn't set!"))?;
+    }
+    Err(format!("The texture variable {texture} refers to itself!"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use crate::resource::resource::LoadedResource;
+
+    use super::*;
+
+    const CUBE_ALL: &str = r##"{
+        "textures": { "north": "#side", "west": "#side", "south": "#side", "east": "#side", "down": "#all", "up": "#all", "side": "#all" },
+        "elements": [{
+            "from": [0, 0, 0], "to": [16, 16, 16],
+            "faces": {
+                "north": { "texture": "#north", "cullface": "north" },
+                "west": { "texture": "#west", "cullface": "west" },
+                "down": { "texture": "#down", "cullface": "down" },
+                "south": { "texture": "#south", "cullface": "south" },
+                "east": { "texture": "#east", "cullface": "east" },
+                "up": { "texture": "#up", "cullface": "up" }
+            }
+        }]
+    }"##;
+
+    fn register_textures() -> [TextureId; 2] {
+        static REGISTER: Once = Once::new();
+        REGISTER.call_once(|| {
+            for name in ["model_test_stone", "model_test_dirt"] {
+                let image = image::DynamicImage::new_rgba8(16, 16);
+                texture_system::add_named_texture(name, LoadedResource::TEXTURE(image)).unwrap();
+            }
+        });
+        ["model_test_stone", "model_test_dirt"].map(|name| texture_system::texture_id(name).unwrap())
+    }
+
+    fn resolve(file_name: &str, files: &[(&str, &str)]) -> Result<VoxelModel, String> {
+        resolve_model(file_name, |file_name| {
+            let (_, data) = files
+                .iter()
+                .find(|(name, _)| *name == file_name)
+                .ok_or_else(|| format!("No model {file_name}"))?;
+            serde_json::from_str(data).map_err(|err| err.to_string())
+        })
+    }
+
+    #[test]
+    fn rotate_turns_counterclockwise_around_the_axis() {
+        assert_eq!(rotate([1, 0, 0], Axis::Y, 1, 0), [0, 0, -1]);
+        assert_eq!(rotate([0, 1, 0], Axis::X, 1, 0), [0, 0, 1]);
+        assert_eq!(rotate([1, 0, 0], Axis::Z, 1, 0), [0, 1, 0]);
+        assert_eq!(rotate([0, 0, 0], Axis::Y, 1, 16), [0, 0, 16]);
+        assert_eq!(rotate([3, 5, 7], Axis::X, 2, 16), [3, 11, 9]);
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            assert_eq!(rotate([3, 5, 7], axis, 4, 16), [3, 5, 7]);
+        }
+    }
+
+    #[test]
+    fn resolve_texture_follows_variables() {
+        let [stone, _] = register_textures();
+        let textures = HashMap::from(
+            [("side", "#all"), ("all", "model_test_stone"), ("loop", "#other"), ("other", "#loop"), ("unknown", "model_test_missing")]
+                .map(|(variable, texture)| (variable.to_string(), texture.to_string())),
+        );
+        assert_eq!(resolve_texture("model_test_stone", &textures), Ok(stone));
+        assert_eq!(resolve_texture("#side", &textures), Ok(stone));
+        assert!(resolve_texture("#unset", &textures).is_err());
+        assert!(resolve_texture("#unknown", &textures).is_err());
+        assert!(resolve_texture("#loop", &textures).is_err());
+    }
+
+    #[test]
+    fn models_inheriting_from_themselves_are_rejected() {
+        let files = [("a.json", r#"{ "parent": "b" }"#), ("b.json", r#"{ "parent": "a" }"#), ("c.json", r#"{ "parent": "c" }"#)];
+        assert!(resolve("a.json", &files).unwrap_err().contains("inherits from itself"));
+        assert!(resolve("c.json", &files).unwrap_err().contains("inherits from itself"));
+        assert!(resolve("d.json", &files).is_err());
+    }
+
+    #[test]
+    fn full_boxes_culled_on_every_side_collapse_into_cubes() {
+        let [stone, dirt] = register_textures();
+        let files = [
+            ("cube_all.json", CUBE_ALL),
+            ("stone.json", r#"{ "parent": "cube_all", "textures": { "all": "model_test_stone", "up": "model_test_dirt" } }"#),
+            (
+                "slab.json",
+                r##"{ "textures": { "all": "model_test_stone" }, "elements": [{ "from": [0, 0, 0], "to": [16, 8, 16], "faces": { "up": { "texture": "#all" } } }] }"##,
+            ),
+        ];
+
+        let Ok(VoxelModel::CUBE(faces)) = resolve("stone.json", &files) else { panic!("stone isn't a cube") };
+        for (norm, face) in Norm::VALUES.into_iter().zip(faces) {
+            assert_eq!(face.norm, norm);
+            assert_eq!(face.tex_id, if norm == Norm::UP { dirt } else { stone });
+        }
+        let Ok(VoxelModel::BOXES(boxes)) = resolve("slab.json", &files) else { panic!("slab isn't made of boxes") };
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].max, [16, 8, 16]);
+        // The unresolved variables of the parent alone fail.
+        assert!(resolve("cube_all.json", &files).is_err());
+    }
+}
@@ -1,14 +1,49 @@
+use std::{collections::BTreeMap, ops::Range};
+
 use super::voxelmodel::VoxelModel;
 
-#[derive(Clone, Copy, Debug)]
+/// How the texture of a voxel covers what lies behind it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Transparency {
+    #[default]
+    OPAQUE = 0,
+    /// Texels are either opaque or fully transparent, the transparent ones are discarded.
+    CUTOUT = 1,
+    /// Blended over the opaque faces behind it, drawn back to front in a second pass. Only cubes can be
+    /// translucent, as shapes are drawn in the opaque pass.
+    TRANSLUCENT = 2,
+}
+
+impl Transparency {
+    pub const VALUES: [Self; 3] = [Transparency::OPAQUE, Transparency::CUTOUT, Transparency::TRANSLUCENT];
+}
+
+#[derive(Clone, Debug)]
 pub struct Voxel {
     pub model: Option<VoxelModel>,
+    /// The block light level the voxel shines with, from 0 to `MAX_LIGHT`.
+    pub emission: u8,
+    pub transparency: Transparency,
+}
+
+impl Voxel {
+    /// Whether the voxel is a cube hiding everything behind it.
+    pub fn is_opaque(&self) -> bool {
+        self.model.as_ref().is_some_and(|model| model.is_cube()) && self.transparency == Transparency::OPAQUE
+    }
+
+    /// Whether light stops at the voxel instead of spreading through it.
+    pub fn blocks_light(&self) -> bool {
+        self.is_opaque()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct VoxelRegistry {
     last_id: usize,
     lookup: Vec<Voxel>,
+    names: Vec<String>,
+    ids: BTreeMap<String, usize>,
 }
 
 impl VoxelRegistry {
@@ -16,11 +51,22 @@ impl VoxelRegistry {
         VoxelRegistry {
             last_id: 0,
             lookup: Vec::new(),
+            names: Vec::new(),
+            ids: BTreeMap::new(),
         }
     }
 
-    pub fn register(&mut self, voxel: Voxel) -> usize {
+    /// Registers a voxel under a unique name, which identifies it independently of the registration order.
+    /// Panics if the name is taken or a voxel whose model isn't a cube is `Transparency::TRANSLUCENT`.
+    pub fn register(&mut self, name: &str, voxel: Voxel) -> usize {
+        assert!(!self.ids.contains_key(name), "A voxel named {name} is already registered!");
+        assert!(
+            !(voxel.transparency == Transparency::TRANSLUCENT && voxel.model.as_ref().is_some_and(|model| !model.is_cube())),
+            "The voxel {name} can't be translucent, only cubes can be!"
+        );
         self.lookup.insert(self.last_id, voxel);
+        self.names.insert(self.last_id, name.to_string());
+        self.ids.insert(name.to_string(), self.last_id);
         self.last_id += 1;
 
         self.last_id - 1
@@ -30,4 +76,67 @@ impl VoxelRegistry {
     pub fn get(&self, id: usize) -> &Voxel {
         unsafe { self.lookup.get_unchecked(id) }
     }
+
+    /// The ids of every registered voxel.
+    pub fn ids(&self) -> Range<usize> {
+        0..self.last_id
+    }
+
+    pub fn name(&self, id: usize) -> &str {
+        &self.names[id]
+    }
+
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.ids.get(name).copied()
+    }
+}
+
+/// The voxels every test registers through `register`, as the tests share the global registry.
+#[cfg(test)]
+pub(crate) mod test_voxels {
+    use std::sync::Once;
+
+    use crate::{voxel::voxelmodel::VoxelModel, world::light::MAX_LIGHT, VOXEL_REGISTRY};
+
+    use super::{Transparency, Voxel};
+
+    pub const AIR: usize = 0;
+    pub const STONE: usize = 1;
+    pub const DIRT: usize = 2;
+    pub const GLASS: usize = 3;
+    pub const LEAVES: usize = 4;
+    pub const SLAB: usize = 5;
+    pub const LAMP: usize = 6;
+
+    pub fn register() {
+        static REGISTER: Once = Once::new();
+        REGISTER.call_once(|| {
+            let voxels = [
+                ("air", None, 0, Transparency::OPAQUE),
+                ("stone", Some(VoxelModel::all(0)), 0, Transparency::OPAQUE),
+                ("dirt", Some(VoxelModel::all(1)), 0, Transparency::OPAQUE),
+                ("glass", Some(VoxelModel::all(2)), 0, Transparency::TRANSLUCENT),
+                ("leaves", Some(VoxelModel::all(3)), 0, Transparency::CUTOUT),
+                ("slab", Some(VoxelModel::slab(0)), 0, Transparency::OPAQUE),
+                ("lamp", Some(VoxelModel::all(4)), MAX_LIGHT, Transparency::OPAQUE),
+            ];
+            for (id, (name, model, emission, transparency)) in voxels.into_iter().enumerate() {
+                let registered = unsafe { VOXEL_REGISTRY.register(name, Voxel { model, emission, transparency }) };
+                assert_eq!(registered, id);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "can't be translucent")]
+    fn translucent_shapes_are_rejected() {
+        let mut registry = VoxelRegistry::new();
+        registry.register("glass", Voxel { model: Some(VoxelModel::all(0)), emission: 0, transparency: Transparency::TRANSLUCENT });
+        registry.register("glass_slab", Voxel { model: Some(VoxelModel::slab(0)), emission: 0, transparency: Transparency::TRANSLUCENT });
+    }
 }
@@ -1,3 +1,6 @@
+use serde::Deserialize;
+use ultraviolet::IVec3;
+
 use crate::resource::texture_system::TextureId;
 
 #[derive(Clone, Copy, Debug)]
@@ -6,7 +9,8 @@ pub struct VoxelFace {
     pub tex_id: TextureId,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Norm {
     NORTH = 0,
@@ -28,6 +32,36 @@ impl Norm {
         Norm::EAST,
         Norm::UP,
     ];
+    /// The offset to the neighboring voxel each normal faces, in the order of `VALUES`.
+    pub const OFFSETS: [IVec3; 6] = [
+        IVec3::new(0, 0, -1),
+        IVec3::new(-1, 0, 0),
+        IVec3::new(0, -1, 0),
+        IVec3::new(0, 0, 1),
+        IVec3::new(1, 0, 0),
+        IVec3::new(0, 1, 0),
+    ];
     pub const BITS: u8 = 3;
     pub const BIT_MASK: u8 = (1 << Norm::BITS) - 1;
 }
+
+/// The corners of a face in the order of `cell.wgsl`'s `vertex_pos_lookup`, leaving out the two repeated
+/// corners of its triangle list, indexed by `Norm`.
+pub const FACE_CORNERS: [[[u8; 3]; 4]; 6] = [
+    [[1, 1, 0], [1, 0, 0], [0, 0, 0], [0, 1, 0]],
+    [[0, 1, 0], [0, 0, 0], [0, 0, 1], [0, 1, 1]],
+    [[0, 0, 0], [1, 0, 0], [1, 0, 1], [0, 0, 1]],
+    [[0, 1, 1], [0, 0, 1], [1, 0, 1], [1, 1, 1]],
+    [[1, 1, 1], [1, 0, 1], [1, 0, 0], [1, 1, 0]],
+    [[0, 1, 0], [0, 1, 1], [1, 1, 1], [1, 1, 0]],
+];
+/// The axes (0 = x, 1 = y, 2 = z) along the normal, the width and the height of a face, in the order
+/// of `Norm::VALUES`. The width and height axes match the texture coordinates of `cell.wgsl`.
+pub const FACE_AXES: [[usize; 3]; 6] = [
+    [2, 0, 1],
+    [0, 2, 1],
+    [1, 2, 0],
+    [2, 0, 1],
+    [0, 2, 1],
+    [1, 0, 2],
+];
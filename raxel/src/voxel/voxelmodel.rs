@@ -1,41 +1,79 @@
 use crate::resource::texture_system::TextureId;
 
-use super::voxelface::{Norm, VoxelFace};
+use super::voxelface::{Norm, VoxelFace, FACE_AXES, FACE_CORNERS};
 
+/// The length of a voxel in the units `ModelBox`es are measured in.
+pub const MODEL_UNITS: u8 = 16;
+
+/// A face of a `ModelBox`.
+#[derive(Clone, Copy, Debug)]
+pub struct ModelFace {
+    pub tex_id: TextureId,
+    /// The rectangle u0, v0, u1, v1 of the texture shown on the face in sixteenths, with v pointing up
+    /// the image. u0 and v0 lie at the second corner of the face in `FACE_CORNERS`.
+    pub uv: [u8; 4],
+    /// The side of the voxel whose opaque neighbor hides the face, `None` for faces that are always drawn.
+    pub cull: Option<Norm>,
+}
+
+/// An axis aligned box inside a voxel, measured in sixteenths of a voxel.
 #[derive(Clone, Copy, Debug)]
-#[repr(transparent)]
-pub struct VoxelModel(pub [VoxelFace; 6]);
+pub struct ModelBox {
+    pub min: [u8; 3],
+    pub max: [u8; 3],
+    /// The faces of the box in the order of `Norm::VALUES`, `None` for faces that are never seen.
+    pub faces: [Option<ModelFace>; 6],
+}
+
+impl ModelBox {
+    /// A box with `tex_id` on every face, the faces lying on the border of the voxel are hidden by
+    /// an opaque neighbor on that side.
+    pub fn new(min: [u8; 3], max: [u8; 3], tex_id: TextureId) -> Self {
+        assert!(
+            (0..3).all(|axis| min[axis] < max[axis] && max[axis] <= MODEL_UNITS),
+            "a model box has to lie inside the voxel and can't be empty!"
+        );
+        let faces = Norm::VALUES.map(|norm| {
+            let offset = Norm::OFFSETS[norm as usize];
+            let axis = (0..3).find(|axis| offset[*axis] != 0).unwrap();
+            let on_border = if offset[axis] < 0 { min[axis] == 0 } else { max[axis] == MODEL_UNITS };
+            Some(ModelFace {
+                tex_id,
+                uv: Self::default_uv(min, max, norm),
+                cull: on_border.then_some(norm),
+            })
+        });
+        Self { min, max, faces }
+    }
+
+    /// The part of the texture a full cube face would show at the place of the box's face on the side `norm`.
+    pub fn default_uv(min: [u8; 3], max: [u8; 3], norm: Norm) -> [u8; 4] {
+        let norm = norm as usize;
+        let [_, width_axis, height_axis] = FACE_AXES[norm];
+        let tex_range = |corner: usize, axis: usize| match FACE_CORNERS[norm][corner][axis] {
+            0 => [min[axis], max[axis]],
+            _ => [MODEL_UNITS - max[axis], MODEL_UNITS - min[axis]],
+        };
+        let [u0, u1] = tex_range(0, width_axis);
+        let [v0, v1] = tex_range(1, height_axis);
+        [u0, v0, u1, v1]
+    }
+}
+
+/// The shape of a voxel.
+#[derive(Clone, Debug)]
+pub enum VoxelModel {
+    /// A full cube, meshed into the compact stream of cube faces.
+    CUBE([VoxelFace; 6]),
+    /// Boxes inside the voxel, like slabs, stairs and fences.
+    BOXES(Vec<ModelBox>),
+    /// Two quads crossing diagonally through the voxel, seen from both sides, like plants.
+    CROSS(TextureId),
+}
 
 impl VoxelModel {
     pub fn all(tex_id: TextureId) -> Self {
-        Self {
-            0: [
-                VoxelFace {
-                    norm: Norm::NORTH,
-                    tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::WEST,
-                    tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::DOWN,
-                    tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::SOUTH,
-                    tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::EAST,
-                    tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::UP,
-                    tex_id,
-                },
-            ],
-        }
+        Self::top_bottom(tex_id, tex_id, tex_id)
     }
 
     pub fn top_bottom(
@@ -43,33 +81,54 @@ impl VoxelModel {
         top_tex_id: TextureId,
         side_tex_id: TextureId,
     ) -> Self {
-        Self {
-            0: [
-                VoxelFace {
-                    norm: Norm::NORTH,
-                    tex_id: side_tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::WEST,
-                    tex_id: side_tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::DOWN,
-                    tex_id: bottom_tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::SOUTH,
-                    tex_id: side_tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::EAST,
-                    tex_id: side_tex_id,
-                },
-                VoxelFace {
-                    norm: Norm::UP,
-                    tex_id: top_tex_id,
-                },
-            ],
+        VoxelModel::CUBE(Norm::VALUES.map(|norm| VoxelFace {
+            norm,
+            tex_id: match norm {
+                Norm::DOWN => bottom_tex_id,
+                Norm::UP => top_tex_id,
+                _ => side_tex_id,
+            },
+        }))
+    }
+
+    /// The lower half of a cube.
+    pub fn slab(tex_id: TextureId) -> Self {
+        VoxelModel::BOXES(vec![ModelBox::new([0, 0, 0], [16, 8, 16], tex_id)])
+    }
+
+    /// A slab with a step on top, on the half of the voxel towards the horizontal `facing`.
+    pub fn stairs(tex_id: TextureId, facing: Norm) -> Self {
+        let offset = Norm::OFFSETS[facing as usize];
+        let mut step_min = [0, 8, 0];
+        let mut step_max = [16, 16, 16];
+        for axis in [0, 2] {
+            match offset[axis] {
+                -1 => step_max[axis] = 8,
+                1 => step_min[axis] = 8,
+                _ => {}
+            }
+        }
+        VoxelModel::BOXES(vec![
+            ModelBox::new([0, 0, 0], [16, 8, 16], tex_id),
+            ModelBox::new(step_min, step_max, tex_id),
+        ])
+    }
+
+    pub fn cross(tex_id: TextureId) -> Self {
+        VoxelModel::CROSS(tex_id)
+    }
+
+    /// Whether the model is a full cube.
+    pub fn is_cube(&self) -> bool {
+        matches!(self, VoxelModel::CUBE(_))
+    }
+
+    /// The textures used by the model.
+    pub fn textures(&self) -> Vec<TextureId> {
+        match self {
+            VoxelModel::CUBE(faces) => faces.iter().map(|face| face.tex_id).collect(),
+            VoxelModel::BOXES(boxes) => boxes.iter().flat_map(|model_box| model_box.faces.iter().flatten().map(|face| face.tex_id)).collect(),
+            VoxelModel::CROSS(tex_id) => vec![*tex_id],
         }
     }
 }
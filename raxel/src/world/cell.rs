@@ -1,24 +1,45 @@
-use ultraviolet::UVec3;
+use ultraviolet::IVec3;
+
+use crate::mesh::{mesher::PackedFace, shape::ShapeQuad};
+
+use super::{light::LightStorage, palette::PaletteStorage};
 
 pub const CELL_LENGTH: usize = 32;
 pub const CELL_SIZE: usize = CELL_LENGTH * CELL_LENGTH * CELL_LENGTH;
-pub const CELL_BUFFER_SIZE: u64 = (core::mem::size_of::<u32>() * 6 * CELL_SIZE) as u64;
+/// The most opaque and cutout faces of a cell that are drawn. Opaque voxels mesh to at most this many
+/// faces, reached by a checkerboard of opaque voxels and air. A checkerboard of two different cutout voxels
+/// shows the faces between them on both sides and meshes to twice as many, the cutout faces past the
+/// limit are left out.
+pub const MAX_CELL_FACES: usize = 3 * CELL_SIZE;
+pub const CELL_BUFFER_SIZE: u64 = (core::mem::size_of::<PackedFace>() * MAX_CELL_FACES) as u64;
+/// The most translucent faces of a cell that are drawn, translucent voxels are expected to be rare.
+pub const MAX_TRANSLUCENT_CELL_FACES: usize = CELL_SIZE / 4;
+pub const TRANSLUCENT_CELL_BUFFER_SIZE: u64 = (core::mem::size_of::<PackedFace>() * MAX_TRANSLUCENT_CELL_FACES) as u64;
+/// The most quads of a cell's non-cube voxels that are drawn.
+pub const MAX_CELL_SHAPE_QUADS: usize = CELL_SIZE / 4;
+pub const SHAPE_CELL_BUFFER_SIZE: u64 = (core::mem::size_of::<ShapeQuad>() * MAX_CELL_SHAPE_QUADS) as u64;
 
 pub const CELL_X_MASK: usize = Cell::encode(1, 0, 0);
 pub const CELL_Y_MASK: usize = Cell::encode(0, 1, 0);
 pub const CELL_Z_MASK: usize = Cell::encode(0, 0, 1);
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Cell {
-    pub voxels: [usize; CELL_SIZE],
-    pub pos: UVec3,
+    pub voxels: PaletteStorage,
+    pub pos: IVec3,
+    /// The sky and block light of every voxel, computed by `LightUpdate` while the cell is loaded.
+    pub light: LightStorage,
+    /// Set when the cell's voxels changed since it was last meshed.
+    pub dirty: bool,
 }
 
 impl Cell {
-    pub fn new(pos: UVec3) -> Self {
+    pub fn new(pos: IVec3) -> Self {
         Self {
-            voxels: [0; CELL_SIZE],
+            voxels: PaletteStorage::uniform(0),
             pos: pos,
+            light: LightStorage::new(),
+            dirty: false,
         }
     }
 
@@ -28,14 +49,10 @@ impl Cell {
     }
 
     pub fn set(&mut self, x: u8, y: u8, z: u8, id: usize) {
-        unsafe {
-            *self
-                .voxels
-                .get_unchecked_mut(Self::encode(x, y, z) as usize) = id;
-        }
+        self.voxels.set(Self::encode(x, y, z), id);
     }
 
     pub fn get(&self, x: u8, y: u8, z: u8) -> usize {
-        unsafe { *self.voxels.get_unchecked(Self::encode(x, y, z) as usize) }
+        self.voxels.get(Self::encode(x, y, z))
     }
 }
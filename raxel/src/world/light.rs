@@ -0,0 +1,439 @@
+use std::collections::{HashMap, VecDeque};
+
+use ultraviolet::IVec3;
+
+use crate::{voxel::voxelface::Norm, VOXEL_REGISTRY};
+
+use super::cell::{Cell, CELL_LENGTH, CELL_SIZE};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Sky light shines down from above the loaded cells, block light is emitted by voxels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightChannel {
+    SKY = 0,
+    BLOCK = 1,
+}
+
+impl LightChannel {
+    pub const VALUES: [Self; 2] = [LightChannel::SKY, LightChannel::BLOCK];
+}
+
+/// The light level of every voxel of a cell, sky light in the low four bits and block light in the high four bits.
+#[derive(Clone, Debug)]
+pub struct LightStorage {
+    data: Box<[u8; CELL_SIZE]>,
+}
+
+impl LightStorage {
+    pub fn new() -> Self {
+        Self {
+            data: vec![0; CELL_SIZE].into_boxed_slice().try_into().unwrap(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, idx: usize, channel: LightChannel) -> u8 {
+        (unsafe { *self.data.get_unchecked(idx) } >> (channel as u8 * 4)) & MAX_LIGHT
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, idx: usize, channel: LightChannel, level: u8) {
+        let shift = channel as u8 * 4;
+        let packed = unsafe { self.data.get_unchecked_mut(idx) };
+        *packed = (*packed & !(MAX_LIGHT << shift)) | (level << shift);
+    }
+
+    /// Both light levels of a voxel as stored, sky light in the low four bits.
+    #[inline(always)]
+    pub fn packed(&self, idx: usize) -> u8 {
+        unsafe { *self.data.get_unchecked(idx) }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+}
+
+impl Default for LightStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const DOWN: usize = Norm::DOWN as usize;
+const LAST: i32 = CELL_LENGTH as i32 - 1;
+
+fn blocks_light(id: usize) -> bool {
+    unsafe { VOXEL_REGISTRY.get(id).blocks_light() }
+}
+
+fn emission(id: usize) -> u8 {
+    unsafe { VOXEL_REGISTRY.get(id).emission }
+}
+
+fn split(pos: IVec3) -> (IVec3, usize) {
+    let cell_pos = pos.map(|value| value.div_euclid(CELL_LENGTH as i32));
+    let local = pos - cell_pos * CELL_LENGTH as i32;
+    (cell_pos, Cell::encode(local.x as u8, local.y as u8, local.z as u8))
+}
+
+/// The light a voxel with `level` passes on to its neighbor in direction `norm`. Full sky light
+/// travels down without fading, so open shafts are lit all the way to the bottom.
+#[inline(always)]
+fn spread(channel: LightChannel, norm: usize, level: u8) -> u8 {
+    if channel == LightChannel::SKY && norm == DOWN && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// A cell and its loaded neighbors, so that the voxels around the cell are looked up without hashing
+/// the position of each.
+struct Neighborhood<'a> {
+    cell: &'a Cell,
+    neighbors: [Option<&'a Cell>; 6],
+}
+
+impl Neighborhood<'_> {
+    /// The voxel and light at `local`, relative to the origin of the cell. `None` if it lies in a
+    /// neighbor that isn't loaded or past an edge or a corner of the cell.
+    fn get(&self, local: IVec3, channel: LightChannel) -> Option<(usize, u8)> {
+        let offset = local.map(|value| value.div_euclid(CELL_LENGTH as i32));
+        let cell = if offset == IVec3::zero() {
+            self.cell
+        } else {
+            self.neighbors[Norm::OFFSETS.iter().position(|norm_offset| *norm_offset == offset)?]?
+        };
+        let local = local - offset * CELL_LENGTH as i32;
+        let idx = Cell::encode(local.x as u8, local.y as u8, local.z as u8);
+        Some((cell.voxels.get(idx), cell.light.get(idx, channel)))
+    }
+
+    /// Whether the light at `local` would raise the light of a neighbor, so it has to be propagated.
+    fn is_frontier(&self, local: IVec3, channel: LightChannel) -> bool {
+        let Some((_, level)) = self.get(local, channel) else { return false };
+        if level == 0 {
+            return false;
+        }
+        Norm::OFFSETS.iter().enumerate().any(|(norm, offset)| {
+            self.get(local + *offset, channel)
+                .is_some_and(|(id, neighbor_level)| !blocks_light(id) && neighbor_level < spread(channel, norm, level))
+        })
+    }
+}
+
+/// Light propagation over the loaded cells, cells whose light changes are marked dirty.
+pub struct LightUpdate<'a> {
+    cells: &'a mut HashMap<IVec3, Cell>,
+}
+
+impl<'a> LightUpdate<'a> {
+    pub fn new(cells: &'a mut HashMap<IVec3, Cell>) -> Self {
+        Self { cells }
+    }
+
+    /// The voxel and light at `pos`, `None` if its cell isn't loaded.
+    fn get(&self, pos: IVec3, channel: LightChannel) -> Option<(usize, u8)> {
+        let (cell_pos, idx) = split(pos);
+        let cell = self.cells.get(&cell_pos)?;
+        Some((cell.voxels.get(idx), cell.light.get(idx, channel)))
+    }
+
+    /// Sets the light at `pos` and marks the cells whose mesh samples it as dirty.
+    fn set(&mut self, pos: IVec3, channel: LightChannel, level: u8) {
+        let (cell_pos, idx) = split(pos);
+        let Some(cell) = self.cells.get_mut(&cell_pos) else { return };
+        cell.light.set(idx, channel, level);
+        cell.dirty = true;
+
+        // Faces of the neighboring cell that face this voxel are lit by it.
+        let local = pos - cell_pos * CELL_LENGTH as i32;
+        for (axis, offset) in [IVec3::unit_x(), IVec3::unit_y(), IVec3::unit_z()].into_iter().enumerate() {
+            let neighbor = match local[axis] {
+                0 => cell_pos - offset,
+                LAST => cell_pos + offset,
+                _ => continue,
+            };
+            if let Some(neighbor_cell) = self.cells.get_mut(&neighbor) {
+                neighbor_cell.dirty = true;
+            }
+        }
+    }
+
+    /// Whether `pos` lies in the top layer of a cell without a loaded cell above, which is lit by the open sky.
+    fn under_open_sky(&self, pos: IVec3) -> bool {
+        let (cell_pos, _) = split(pos);
+        pos.y - cell_pos.y * CELL_LENGTH as i32 == LAST && !self.cells.contains_key(&(cell_pos + IVec3::unit_y()))
+    }
+
+    /// Spreads the light of the voxels in `queue` until it fades out.
+    pub fn propagate(&mut self, channel: LightChannel, mut queue: VecDeque<IVec3>) {
+        while let Some(pos) = queue.pop_front() {
+            let Some((_, level)) = self.get(pos, channel) else { continue };
+            for (norm, offset) in Norm::OFFSETS.iter().enumerate() {
+                let new_level = spread(channel, norm, level);
+                if new_level == 0 {
+                    continue;
+                }
+                let neighbor = pos + *offset;
+                let Some((id, neighbor_level)) = self.get(neighbor, channel) else { continue };
+                if blocks_light(id) || neighbor_level >= new_level {
+                    continue;
+                }
+                self.set(neighbor, channel, new_level);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    /// Darkens the voxels lit by the voxels in `queue`, which were already darkened and hold their
+    /// previous level. Returns the voxels whose light has to be propagated again to fill the gap.
+    pub fn remove(&mut self, channel: LightChannel, mut queue: VecDeque<(IVec3, u8)>) -> VecDeque<IVec3> {
+        let mut relight = VecDeque::new();
+        while let Some((pos, level)) = queue.pop_front() {
+            for (norm, offset) in Norm::OFFSETS.iter().enumerate() {
+                let neighbor = pos + *offset;
+                let Some((id, neighbor_level)) = self.get(neighbor, channel) else { continue };
+                if neighbor_level == 0 {
+                    continue;
+                }
+
+                // Light the removed voxel passed on is removed too, brighter light has another source.
+                if neighbor_level < level || (spread(channel, norm, level) == MAX_LIGHT && neighbor_level == MAX_LIGHT) {
+                    self.set(neighbor, channel, 0);
+                    queue.push_back((neighbor, neighbor_level));
+                    if let Some(level) = self.source_level(neighbor, id, channel) {
+                        self.set(neighbor, channel, level);
+                        relight.push_back(neighbor);
+                    }
+                } else {
+                    relight.push_back(neighbor);
+                }
+            }
+        }
+        relight
+    }
+
+    /// The light a voxel emits on its own, from the open sky or as a light source.
+    fn source_level(&self, pos: IVec3, id: usize, channel: LightChannel) -> Option<u8> {
+        match channel {
+            LightChannel::SKY if !blocks_light(id) && self.under_open_sky(pos) => Some(MAX_LIGHT),
+            LightChannel::BLOCK if emission(id) > 0 => Some(emission(id)),
+            _ => None,
+        }
+    }
+
+    /// Relights around `pos` after its voxel changed.
+    pub fn update_voxel(&mut self, pos: IVec3) {
+        let Some((id, _)) = self.get(pos, LightChannel::SKY) else { return };
+        for channel in LightChannel::VALUES {
+            let (_, level) = self.get(pos, channel).unwrap();
+            self.set(pos, channel, 0);
+            let mut relight = self.remove(channel, VecDeque::from([(pos, level)]));
+
+            if let Some(level) = self.source_level(pos, id, channel) {
+                self.set(pos, channel, level);
+                relight.push_back(pos);
+            }
+            // An opening is filled with the light of its neighbors.
+            if !blocks_light(id) {
+                relight.extend(Norm::OFFSETS.iter().map(|offset| pos + *offset));
+            }
+            self.propagate(channel, relight);
+        }
+    }
+
+    /// Lights the freshly loaded cell at `cell_pos` and spreads its light into the loaded neighbors and theirs into it.
+    pub fn light_cell(&mut self, cell_pos: IVec3) {
+        let origin = cell_pos * CELL_LENGTH as i32;
+        let above = cell_pos + IVec3::unit_y();
+        let below = cell_pos - IVec3::unit_y();
+
+        let mut sky_removal = VecDeque::new();
+        {
+            let above_cell = self.cells.get(&above).map(|cell| cell.light.clone());
+            let cell = self.cells.get_mut(&cell_pos).unwrap();
+            cell.light.clear();
+
+            // Full sky light falls in from the open sky or from the full sky light of the cell above.
+            for x in 0..CELL_LENGTH as u8 {
+                for z in 0..CELL_LENGTH as u8 {
+                    let lit = above_cell
+                        .as_ref()
+                        .map_or(true, |light| light.get(Cell::encode(x, 0, z), LightChannel::SKY) == MAX_LIGHT);
+                    if !lit {
+                        continue;
+                    }
+                    for y in (0..CELL_LENGTH as u8).rev() {
+                        let idx = Cell::encode(x, y, z);
+                        if blocks_light(cell.voxels.get(idx)) {
+                            break;
+                        }
+                        cell.light.set(idx, LightChannel::SKY, MAX_LIGHT);
+                    }
+                }
+            }
+
+            if cell.voxels.palette().iter().any(|id| emission(*id) > 0) {
+                for idx in 0..CELL_SIZE {
+                    let emission = emission(cell.voxels.get(idx));
+                    if emission > 0 {
+                        cell.light.set(idx, LightChannel::BLOCK, emission);
+                    }
+                }
+            }
+        }
+
+        // The cell below assumed open sky above it, columns this cell blocks are darkened again.
+        if self.cells.contains_key(&below) {
+            for x in 0..CELL_LENGTH as i32 {
+                for z in 0..CELL_LENGTH as i32 {
+                    let pos = origin + IVec3::new(x, 0, z);
+                    let below_pos = pos - IVec3::unit_y();
+                    let (_, level) = self.get(pos, LightChannel::SKY).unwrap();
+                    let (_, below_level) = self.get(below_pos, LightChannel::SKY).unwrap();
+                    if level != MAX_LIGHT && below_level == MAX_LIGHT {
+                        self.set(below_pos, LightChannel::SKY, 0);
+                        sky_removal.push_back((below_pos, MAX_LIGHT));
+                    }
+                }
+            }
+        }
+
+        for channel in LightChannel::VALUES {
+            let mut queue = match channel {
+                LightChannel::SKY => self.remove(channel, std::mem::take(&mut sky_removal)),
+                LightChannel::BLOCK => VecDeque::new(),
+            };
+
+            // Only voxels that can brighten a neighbor need to be propagated, which keeps the
+            // queue small for the common cases of open air and solid ground. Voxels inside the cell
+            // are checked directly, the border layers on both sides with lookups across cells.
+            let neighborhood = Neighborhood {
+                cell: &self.cells[&cell_pos],
+                neighbors: Norm::OFFSETS.map(|offset| self.cells.get(&(cell_pos + offset))),
+            };
+            let cell = neighborhood.cell;
+            for idx in 0..CELL_SIZE {
+                let local = IVec3::new((idx >> 10) as i32, ((idx >> 5) & 31) as i32, (idx & 31) as i32);
+                if local.component_min() == 0 || local.component_max() == LAST {
+                    continue;
+                }
+                let level = cell.light.get(idx, channel);
+                if level == 0 {
+                    continue;
+                }
+                let is_frontier = Norm::OFFSETS.iter().enumerate().any(|(norm, offset)| {
+                    let neighbor = local + *offset;
+                    let neighbor_idx = Cell::encode(neighbor.x as u8, neighbor.y as u8, neighbor.z as u8);
+                    !blocks_light(cell.voxels.get(neighbor_idx)) && cell.light.get(neighbor_idx, channel) < spread(channel, norm, level)
+                });
+                if is_frontier {
+                    queue.push_back(origin + local);
+                }
+            }
+
+            for x in -1..=CELL_LENGTH as i32 {
+                for y in -1..=CELL_LENGTH as i32 {
+                    for z in -1..=CELL_LENGTH as i32 {
+                        let pos = IVec3::new(x, y, z);
+                        let outside = [x, y, z].iter().filter(|value| **value == -1 || **value == CELL_LENGTH as i32).count();
+                        let on_border = pos.component_min() == 0 || pos.component_max() == LAST;
+                        // The edges and corners of the neighbors' border layers don't touch this cell.
+                        if outside > 1 || (outside == 0 && !on_border) {
+                            continue;
+                        }
+                        if neighborhood.is_frontier(pos, channel) {
+                            queue.push_back(origin + pos);
+                        }
+                    }
+                }
+            }
+            self.propagate(channel, queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::voxel::test_voxels::{self, AIR, LAMP, STONE};
+
+    use super::*;
+
+    /// Loads and lights `cells` in order, like the world does as it streams them in.
+    fn lit_cells(cells: impl IntoIterator<Item = Cell>) -> HashMap<IVec3, Cell> {
+        test_voxels::register();
+        let mut loaded = HashMap::new();
+        for cell in cells {
+            let pos = cell.pos;
+            loaded.insert(pos, cell);
+            LightUpdate::new(&mut loaded).light_cell(pos);
+        }
+        loaded
+    }
+
+    fn light(cells: &HashMap<IVec3, Cell>, [x, y, z]: [u8; 3], channel: LightChannel) -> u8 {
+        cells[&IVec3::zero()].light.get(Cell::encode(x, y, z), channel)
+    }
+
+    /// Replaces the voxel at `pos` in the cell at the origin and relights around it.
+    fn set_voxel(cells: &mut HashMap<IVec3, Cell>, [x, y, z]: [u8; 3], id: usize) {
+        cells.get_mut(&IVec3::zero()).unwrap().set(x, y, z, id);
+        LightUpdate::new(cells).update_voxel(IVec3::new(x as i32, y as i32, z as i32));
+    }
+
+    /// A cell full of stone above the origin, so that the sky doesn't light the cell at the origin.
+    fn roof() -> Cell {
+        let mut roof = Cell::new(IVec3::unit_y());
+        for idx in 0..CELL_SIZE {
+            roof.voxels.set(idx, STONE);
+        }
+        roof
+    }
+
+    #[test]
+    fn sky_light_fills_open_columns_down_to_the_first_opaque_voxel() {
+        let mut cell = Cell::new(IVec3::zero());
+        cell.set(5, 20, 5, STONE);
+        let cells = lit_cells([cell]);
+
+        assert!((0..CELL_LENGTH as u8).all(|y| light(&cells, [6, y, 6], LightChannel::SKY) == MAX_LIGHT));
+        assert!((21..CELL_LENGTH as u8).all(|y| light(&cells, [5, y, 5], LightChannel::SKY) == MAX_LIGHT));
+        // Below the stone the light comes in from the side.
+        assert_eq!(light(&cells, [5, 19, 5], LightChannel::SKY), MAX_LIGHT - 1);
+
+        let cells = lit_cells([roof(), Cell::new(IVec3::zero())]);
+        assert!((0..CELL_SIZE).all(|idx| cells[&IVec3::zero()].light.get(idx, LightChannel::SKY) == 0));
+    }
+
+    #[test]
+    fn block_light_fades_with_the_distance_to_the_emitter() {
+        let mut cell = Cell::new(IVec3::zero());
+        cell.set(16, 16, 16, LAMP);
+        let cells = lit_cells([roof(), cell]);
+
+        assert_eq!(light(&cells, [16, 16, 16], LightChannel::BLOCK), MAX_LIGHT);
+        assert_eq!(light(&cells, [21, 16, 16], LightChannel::BLOCK), MAX_LIGHT - 5);
+        assert_eq!(light(&cells, [18, 17, 13], LightChannel::BLOCK), MAX_LIGHT - 6);
+        assert_eq!(light(&cells, [16, 16, 31], LightChannel::BLOCK), 0);
+    }
+
+    #[test]
+    fn removing_the_emitter_or_roofing_a_column_darkens_it() {
+        let mut cell = Cell::new(IVec3::zero());
+        cell.set(16, 16, 16, LAMP);
+        let mut cells = lit_cells([roof(), cell]);
+        set_voxel(&mut cells, [16, 16, 16], AIR);
+        assert!((0..CELL_SIZE).all(|idx| cells[&IVec3::zero()].light.get(idx, LightChannel::BLOCK) == 0));
+
+        let mut cells = lit_cells([Cell::new(IVec3::zero())]);
+        set_voxel(&mut cells, [5, 31, 5], STONE);
+        assert!((0..31).all(|y| light(&cells, [5, y, 5], LightChannel::SKY) == MAX_LIGHT - 1));
+
+        // Taking the roof off lights the column fully again.
+        set_voxel(&mut cells, [5, 31, 5], AIR);
+        assert!((0..CELL_LENGTH as u8).all(|y| light(&cells, [5, y, 5], LightChannel::SKY) == MAX_LIGHT));
+    }
+}
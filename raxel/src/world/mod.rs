@@ -1,2 +1,7 @@
 pub mod cell;
+pub mod light;
+pub mod palette;
+pub mod raycast;
+pub mod region;
+pub mod vox;
 pub mod world;
@@ -0,0 +1,183 @@
+use super::cell::CELL_SIZE;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Voxel ids stored as indices into a palette of the distinct ids of a cell.
+/// Indices are bit-packed with a power of two width so that they never straddle two words,
+/// a storage holding a single id (an all-air or all-stone cell) has no index data at all.
+#[derive(Clone, Debug)]
+pub struct PaletteStorage {
+    palette: Vec<usize>,
+    bits: usize,
+    data: Vec<u64>,
+}
+
+impl PaletteStorage {
+    pub fn uniform(id: usize) -> Self {
+        Self {
+            palette: vec![id],
+            bits: 0,
+            data: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> usize {
+        if self.bits == 0 {
+            return unsafe { *self.palette.get_unchecked(0) };
+        }
+        unsafe { *self.palette.get_unchecked(self.index(idx)) }
+    }
+
+    pub fn set(&mut self, idx: usize, id: usize) {
+        let palette_idx = match self.palette.iter().position(|&value| value == id) {
+            Some(palette_idx) => palette_idx,
+            None => {
+                if self.palette.len() == 1 << self.bits {
+                    self.repack(id);
+                } else {
+                    self.palette.push(id);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits == 0 {
+            return;
+        }
+        self.set_index(idx, palette_idx);
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// The distinct ids of this storage, may contain ids that aren't referenced anymore.
+    pub fn palette(&self) -> &[usize] {
+        &self.palette
+    }
+
+    #[inline(always)]
+    fn index(&self, idx: usize) -> usize {
+        let bit = idx * self.bits;
+        let word = unsafe { *self.data.get_unchecked(bit / WORD_BITS) };
+        ((word >> (bit % WORD_BITS)) & ((1 << self.bits) - 1)) as usize
+    }
+
+    #[inline(always)]
+    fn set_index(&mut self, idx: usize, palette_idx: usize) {
+        let bit = idx * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << (bit % WORD_BITS);
+        let word = unsafe { self.data.get_unchecked_mut(bit / WORD_BITS) };
+        *word = (*word & !mask) | ((palette_idx as u64) << (bit % WORD_BITS));
+    }
+
+    /// Drops unreferenced palette entries, appends `new_id` and re-encodes the indices with the smallest width that fits.
+    fn repack(&mut self, new_id: usize) {
+        let mut used = vec![false; self.palette.len()];
+        if self.bits == 0 {
+            used[0] = true;
+        } else {
+            for idx in 0..CELL_SIZE {
+                used[self.index(idx)] = true;
+            }
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::with_capacity(self.palette.len() + 1);
+        for (old_idx, &id) in self.palette.iter().enumerate() {
+            if used[old_idx] {
+                remap[old_idx] = palette.len();
+                palette.push(id);
+            }
+        }
+        palette.push(new_id);
+
+        let mut bits = 0;
+        while 1 << bits < palette.len() {
+            bits = if bits == 0 { 1 } else { bits * 2 };
+        }
+
+        let mut repacked = Self {
+            palette,
+            bits,
+            data: vec![0; CELL_SIZE * bits / WORD_BITS],
+        };
+        if bits != 0 {
+            for idx in 0..CELL_SIZE {
+                let old_idx = if self.bits == 0 { 0 } else { self.index(idx) };
+                repacked.set_index(idx, remap[old_idx]);
+            }
+        }
+        *self = repacked;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_reads_back(storage: &PaletteStorage, expected: &[usize]) {
+        for (idx, id) in expected.iter().enumerate() {
+            assert_eq!(storage.get(idx), *id, "the voxel at {idx} changed with {} bits", storage.bits);
+        }
+    }
+
+    #[test]
+    fn a_uniform_storage_has_no_index_data() {
+        let mut storage = PaletteStorage::uniform(7);
+        assert!(storage.is_uniform());
+        assert!(storage.data.is_empty());
+        assert_eq!(storage.get(0), 7);
+        assert_eq!(storage.get(CELL_SIZE - 1), 7);
+
+        // Setting the id it already holds keeps it uniform.
+        storage.set(12, 7);
+        assert!(storage.is_uniform());
+
+        storage.set(12, 3);
+        assert!(!storage.is_uniform());
+        assert_eq!(storage.palette(), [7, 3]);
+        let mut expected = vec![7; CELL_SIZE];
+        expected[12] = 3;
+        assert_reads_back(&storage, &expected);
+    }
+
+    #[test]
+    fn every_index_reads_back_after_repacking_at_each_width() {
+        let mut storage = PaletteStorage::uniform(0);
+        let mut expected = vec![0; CELL_SIZE];
+        let mut widths = Vec::new();
+        // Every new id is kept referenced, so the palette grows by one each time.
+        for id in 1..=256 {
+            let idx = id * 97 % CELL_SIZE;
+            storage.set(idx, id);
+            expected[idx] = id;
+            assert_eq!(storage.palette().len(), id + 1);
+            if widths.last() != Some(&storage.bits) {
+                widths.push(storage.bits);
+                assert_reads_back(&storage, &expected);
+            }
+        }
+        assert_eq!(widths, [1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn repacking_drops_unreferenced_ids() {
+        let mut storage = PaletteStorage::uniform(0);
+        for id in 1..4 {
+            storage.set(id, id);
+        }
+        assert_eq!(storage.bits, 2);
+
+        // Ids 1 and 2 are overwritten, so the fifth id fits in the two bits again.
+        storage.set(1, 0);
+        storage.set(2, 0);
+        storage.set(10, 4);
+        assert_eq!(storage.bits, 2);
+        assert_eq!(storage.palette(), [0, 3, 4]);
+        let mut expected = vec![0; CELL_SIZE];
+        expected[3] = 3;
+        expected[10] = 4;
+        assert_reads_back(&storage, &expected);
+    }
+}
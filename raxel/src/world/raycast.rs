@@ -0,0 +1,126 @@
+use ultraviolet::{IVec3, Vec3};
+
+use crate::{voxel::voxelface::Norm, VOXEL_REGISTRY};
+
+use super::{cell::CELL_LENGTH, world::World};
+
+/// The solid voxel a ray hit first.
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub pos: IVec3,
+    pub voxel_id: usize,
+    /// The face of the voxel the ray entered through.
+    pub norm: Norm,
+    /// The distance along the ray to where it entered the voxel.
+    pub distance: f32,
+}
+
+/// The face entered when stepping along an axis in the positive direction, and in the negative direction.
+const ENTERED_FACES: [[Norm; 2]; 3] = [[Norm::WEST, Norm::EAST], [Norm::DOWN, Norm::UP], [Norm::NORTH, Norm::SOUTH]];
+
+impl World {
+    /// Walks the voxels along the ray from `origin` towards `dir` (Amanatides and Woo's traversal) and
+    /// returns the first one with a model within `max_dist`. Voxels in cells that aren't loaded count as air,
+    /// so the walk stops once the ray left the loaded cells, even with an infinite `max_dist`. A NaN
+    /// `max_dist` never hits.
+    /// `dir` doesn't need to be normalized, so `Camera::direction` can be passed as is.
+    /// Every voxel with a model counts as a full cube, so rays also hit the empty parts of slabs and
+    /// plants and the hit face and distance are those of the voxel's cube.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3, max_dist: f32) -> Option<RaycastHit> {
+        // A zero direction normalizes to NaNs.
+        let dir = dir.normalized();
+        if !dir.mag_sq().is_finite() || max_dist.is_nan() || !origin.mag_sq().is_finite() {
+            return None;
+        }
+
+        // The first and last voxel of the box around the loaded cells.
+        let first_cell = self.cells.keys().copied().reduce(IVec3::min_by_component)?;
+        let last_cell = self.cells.keys().copied().reduce(IVec3::max_by_component)?;
+        let loaded_min = (first_cell * CELL_LENGTH as i32).as_array();
+        let loaded_max = ((last_cell + IVec3::one()) * CELL_LENGTH as i32 - IVec3::one()).as_array();
+
+        let origin = *origin.as_array();
+        let dir = *dir.as_array();
+        let mut pos = origin.map(|value| value.floor() as i32);
+        let step = dir.map(|value| if value > 0.0 { 1 } else if value < 0.0 { -1 } else { 0 });
+        // The distance along the ray between two voxel borders, and to the next border, on each axis.
+        let t_delta = dir.map(|value| (1.0 / value).abs());
+        let mut t_max = [0, 1, 2].map(|axis| match step[axis] {
+            1 => (pos[axis] as f32 + 1.0 - origin[axis]) / dir[axis],
+            -1 => (origin[axis] - pos[axis] as f32) / -dir[axis],
+            _ => f32::INFINITY,
+        });
+
+        // A ray starting inside a voxel reports the face its direction points away from the most.
+        let mut axis = (0..3).max_by(|a, b| dir[*a].abs().total_cmp(&dir[*b].abs())).unwrap();
+        let mut distance = 0.0;
+        loop {
+            // Past the loaded box on an axis the ray doesn't move back along, it never enters the box again.
+            if (0..3).any(|axis| (pos[axis] < loaded_min[axis] && step[axis] <= 0) || (pos[axis] > loaded_max[axis] && step[axis] >= 0)) {
+                return None;
+            }
+
+            let voxel_pos = IVec3::new(pos[0], pos[1], pos[2]);
+            if let Some(voxel_id) = self.get_voxel(voxel_pos) {
+                if unsafe { VOXEL_REGISTRY.get(voxel_id).model.is_some() } {
+                    return Some(RaycastHit {
+                        pos: voxel_pos,
+                        voxel_id,
+                        norm: ENTERED_FACES[axis][(step[axis] < 0) as usize],
+                        distance,
+                    });
+                }
+            }
+
+            axis = (0..3).min_by(|a, b| t_max[*a].total_cmp(&t_max[*b])).unwrap();
+            distance = t_max[axis];
+            if distance > max_dist {
+                return None;
+            }
+            pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::voxel::test_voxels::{self, STONE};
+
+    use super::*;
+
+    /// A single loaded cell at the origin, stone below y = 16.
+    fn world() -> World {
+        test_voxels::register();
+        World::new(0, Box::new(|cell| {
+            for idx in 0..CELL_LENGTH * CELL_LENGTH * CELL_LENGTH {
+                if (idx >> 5) & 31 < 16 {
+                    cell.voxels.set(idx, STONE);
+                }
+            }
+        }))
+    }
+
+    #[test]
+    fn hits_the_first_voxel_with_a_model() {
+        let hit = world().raycast(Vec3::new(4.5, 20.5, 4.5), Vec3::new(0.0, -1.0, 0.0), 10.0).unwrap();
+        assert_eq!(hit.pos, IVec3::new(4, 15, 4));
+        assert_eq!(hit.norm, Norm::UP);
+        assert_eq!(hit.distance, 4.5);
+    }
+
+    #[test]
+    fn stops_at_a_nan_or_short_distance_or_past_the_loaded_cells() {
+        let world = world();
+        let (origin, down) = (Vec3::new(4.5, 20.5, 4.5), Vec3::new(0.0, -1.0, 0.0));
+        assert!(world.raycast(origin, down, f32::NAN).is_none());
+        assert!(world.raycast(origin, down, 2.0).is_none());
+        assert_eq!(world.raycast(origin, down, f32::INFINITY).unwrap().pos, IVec3::new(4, 15, 4));
+        // Rays leaving the loaded cell, and one passing by it, even without a limit.
+        assert!(world.raycast(origin, Vec3::new(0.3, 1.0, 0.2), f32::INFINITY).is_none());
+        assert!(world.raycast(Vec3::new(-10.0, 40.0, 4.5), Vec3::new(1.0, 0.0, 0.0), f32::INFINITY).is_none());
+        // A ray from outside the loaded cells still reaches them.
+        let hit = world.raycast(Vec3::new(-10.5, 8.5, 4.5), Vec3::new(1.0, 0.0, 0.0), 1e9).unwrap();
+        assert_eq!((hit.pos, hit.norm), (IVec3::new(0, 8, 4), Norm::WEST));
+    }
+}
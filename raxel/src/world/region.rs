@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use ultraviolet::IVec3;
+
+use crate::VOXEL_REGISTRY;
+
+use super::{cell::{Cell, CELL_SIZE}, palette::PaletteStorage};
+
+/// Region files group `REGION_LENGTH`³ cells.
+pub const REGION_LENGTH: i32 = 8;
+const REGION_SIZE: usize = (REGION_LENGTH * REGION_LENGTH * REGION_LENGTH) as usize;
+
+const REGION_MAGIC: [u8; 4] = *b"RXRG";
+pub const REGION_FORMAT_VERSION: u32 = 1;
+/// The magic, the format version and an offset and length for every cell of the region.
+const REGION_HEADER_SIZE: usize = 4 + 4 + REGION_SIZE * 8;
+
+/// The compressed cells of a region file, indexed by `cell_index`.
+///
+/// A region file starts with a header of `REGION_MAGIC`, the format version and a table holding
+/// the byte offset and length of every cell, cells that were never saved have a length of zero.
+/// Each cell is compressed on its own, so it can be read without decompressing the rest of the region.
+struct Region {
+    cells: Vec<Option<Vec<u8>>>,
+}
+
+impl Region {
+    fn read(path: &Path) -> io::Result<Self> {
+        let mut cells = vec![None; REGION_SIZE];
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self { cells }),
+            Err(err) => return Err(err),
+        };
+
+        let table = read_header(&data[..REGION_HEADER_SIZE.min(data.len())])?;
+        for (idx, (offset, length)) in table.into_iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let cell = data
+                .get(offset..offset + length)
+                .ok_or_else(|| invalid_data("a cell lies outside of the region file"))?;
+            cells[idx] = Some(cell.to_vec());
+        }
+        Ok(Self { cells })
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut header = Vec::with_capacity(REGION_HEADER_SIZE);
+        header.extend_from_slice(&REGION_MAGIC);
+        header.extend_from_slice(&REGION_FORMAT_VERSION.to_le_bytes());
+
+        let mut offset = REGION_HEADER_SIZE;
+        for cell in &self.cells {
+            let length = cell.as_ref().map_or(0, |cell| cell.len());
+            header.extend_from_slice(&(offset as u32).to_le_bytes());
+            header.extend_from_slice(&(length as u32).to_le_bytes());
+            offset += length;
+        }
+
+        // The region is written next to the old file and swapped in once complete, so a crash while
+        // writing keeps the cells of the old file.
+        let temp_path = path.with_extension("rxr.tmp");
+        let mut file = io::BufWriter::new(File::create(&temp_path)?);
+        file.write_all(&header)?;
+        for cell in self.cells.iter().flatten() {
+            file.write_all(cell)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        drop(file);
+        fs::rename(temp_path, path)
+    }
+}
+
+/// Validates the header of a region file and returns the offset and length of every cell.
+fn read_header(header: &[u8]) -> io::Result<Vec<(usize, usize)>> {
+    if header.len() < REGION_HEADER_SIZE || header[..4] != REGION_MAGIC {
+        return Err(invalid_data("not a region file"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != REGION_FORMAT_VERSION {
+        return Err(invalid_data(&format!("unsupported region format version {version}")));
+    }
+
+    Ok(header[8..]
+        .chunks_exact(8)
+        .map(|entry| {
+            (
+                u32::from_le_bytes(entry[..4].try_into().unwrap()) as usize,
+                u32::from_le_bytes(entry[4..].try_into().unwrap()) as usize,
+            )
+        })
+        .collect())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(super) fn region_pos(cell_pos: IVec3) -> IVec3 {
+    cell_pos.map(|value| value.div_euclid(REGION_LENGTH))
+}
+
+pub(super) fn region_path(dir: &Path, region_pos: IVec3) -> PathBuf {
+    dir.join(format!("r.{}.{}.{}.rxr", region_pos.x, region_pos.y, region_pos.z))
+}
+
+fn cell_index(cell_pos: IVec3) -> usize {
+    let local = cell_pos - region_pos(cell_pos) * REGION_LENGTH;
+    ((local.x * REGION_LENGTH + local.y) * REGION_LENGTH + local.z) as usize
+}
+
+/// Compresses a cell as its palette of voxel names followed by a palette index per voxel,
+/// the indices are left out for cells holding a single voxel.
+fn encode_cell(cell: &Cell) -> io::Result<Vec<u8>> {
+    let mut palette: Vec<usize> = Vec::new();
+    let mut palette_lookup: HashMap<usize, u16> = HashMap::new();
+    let mut indices: Vec<u8> = Vec::with_capacity(CELL_SIZE * 2);
+    for idx in 0..CELL_SIZE {
+        let id = cell.voxels.get(idx);
+        let palette_idx = *palette_lookup.entry(id).or_insert_with(|| {
+            palette.push(id);
+            (palette.len() - 1) as u16
+        });
+        indices.extend_from_slice(&palette_idx.to_le_bytes());
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&(palette.len() as u16).to_le_bytes())?;
+    for id in palette.iter() {
+        let name = unsafe { VOXEL_REGISTRY.name(*id) };
+        encoder.write_all(&(name.len() as u16).to_le_bytes())?;
+        encoder.write_all(name.as_bytes())?;
+    }
+    if palette.len() > 1 {
+        encoder.write_all(&indices)?;
+    }
+    encoder.finish()
+}
+
+fn decode_cell(pos: IVec3, data: &[u8]) -> io::Result<Cell> {
+    fn read_u16(decoder: &mut impl Read) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        decoder.read_exact(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    let mut decoder = ZlibDecoder::new(data);
+
+    let palette_len = read_u16(&mut decoder)? as usize;
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        let mut name = vec![0; read_u16(&mut decoder)? as usize];
+        decoder.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid_data("a voxel name isn't valid UTF-8"))?;
+        let id = unsafe { VOXEL_REGISTRY.id(&name) }
+            .ok_or_else(|| invalid_data(&format!("no voxel named {name} is registered")))?;
+        palette.push(id);
+    }
+
+    let mut cell = Cell::new(pos);
+    match palette.len() {
+        0 => return Err(invalid_data("a cell has an empty palette")),
+        1 => cell.voxels = PaletteStorage::uniform(palette[0]),
+        _ => {
+            let mut indices = vec![0u8; CELL_SIZE * 2];
+            decoder.read_exact(&mut indices)?;
+            for (idx, palette_idx) in indices.chunks_exact(2).enumerate() {
+                let id = palette
+                    .get(u16::from_le_bytes([palette_idx[0], palette_idx[1]]) as usize)
+                    .ok_or_else(|| invalid_data("a voxel lies outside of its cell's palette"))?;
+                cell.voxels.set(idx, *id);
+            }
+        }
+    }
+    Ok(cell)
+}
+
+/// Reads the cell at `pos` from the region files in `dir`, `None` if it was never saved.
+pub fn read_cell(dir: &Path, pos: IVec3) -> io::Result<Option<Cell>> {
+    let mut file = match File::open(region_path(dir, region_pos(pos))) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut header = vec![0; REGION_HEADER_SIZE];
+    file.read_exact(&mut header)?;
+    let (offset, length) = read_header(&header)?[cell_index(pos)];
+    if length == 0 {
+        return Ok(None);
+    }
+
+    let mut data = vec![0; length];
+    file.seek(SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut data)?;
+    decode_cell(pos, &data).map(Some)
+}
+
+/// Writes `cells` to the region files in `dir`, keeping the other cells already stored in those regions.
+pub fn write_cells<'a>(dir: &Path, cells: impl Iterator<Item = &'a Cell>) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let mut regions: HashMap<IVec3, Vec<&Cell>> = HashMap::new();
+    for cell in cells {
+        regions.entry(region_pos(cell.pos)).or_default().push(cell);
+    }
+
+    for (pos, cells) in regions {
+        let path = region_path(dir, pos);
+        let mut region = Region::read(&path)?;
+        for cell in cells {
+            region.cells[cell_index(cell.pos)] = Some(encode_cell(cell)?);
+        }
+        region.write(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::voxel::test_voxels::{self, AIR, DIRT, STONE};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raxel-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn assert_same_voxels(a: &Cell, b: &Cell) {
+        assert_eq!(a.pos, b.pos);
+        assert!((0..CELL_SIZE).all(|idx| a.voxels.get(idx) == b.voxels.get(idx)));
+    }
+
+    #[test]
+    fn cells_survive_a_round_trip() {
+        test_voxels::register();
+        let dir = temp_dir("region-round-trip");
+
+        let mut mixed = Cell::new(IVec3::new(-1, 2, 9));
+        for idx in 0..CELL_SIZE {
+            mixed.voxels.set(idx, [AIR, STONE, DIRT][idx * 7 % 3]);
+        }
+        let mut uniform = Cell::new(IVec3::new(0, 0, 0));
+        uniform.voxels = PaletteStorage::uniform(STONE);
+        write_cells(&dir, [&mixed, &uniform].into_iter()).unwrap();
+
+        assert_same_voxels(&read_cell(&dir, mixed.pos).unwrap().unwrap(), &mixed);
+        assert_same_voxels(&read_cell(&dir, uniform.pos).unwrap().unwrap(), &uniform);
+        // A cell of a written region that was never saved, and one of a region without a file.
+        assert!(read_cell(&dir, IVec3::new(1, 0, 0)).unwrap().is_none());
+        assert!(read_cell(&dir, IVec3::new(100, 0, 0)).unwrap().is_none());
+
+        // Rewriting a region keeps its other cells.
+        let mut changed = Cell::new(IVec3::new(1, 0, 0));
+        changed.set(3, 4, 5, DIRT);
+        write_cells(&dir, [&changed].into_iter()).unwrap();
+        assert_same_voxels(&read_cell(&dir, changed.pos).unwrap().unwrap(), &changed);
+        assert_same_voxels(&read_cell(&dir, uniform.pos).unwrap().unwrap(), &uniform);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_region_files_are_rejected() {
+        test_voxels::register();
+        let dir = temp_dir("region-invalid");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(region_path(&dir, IVec3::zero()), b"not a region").unwrap();
+
+        assert_eq!(read_cell(&dir, IVec3::zero()).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let cell = Cell::new(IVec3::zero());
+        assert_eq!(write_cells(&dir, [&cell].into_iter()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,503 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use ultraviolet::IVec3;
+
+use crate::{resource::texture_system, VOXEL_REGISTRY};
+
+use super::{cell::CELL_LENGTH, world::World};
+
+const VOX_MAGIC: [u8; 4] = *b"VOX ";
+const VOX_VERSION: i32 = 150;
+/// The largest size of a model along each axis.
+pub const VOX_MODEL_LENGTH: u32 = 256;
+
+/// A model of a `.vox` file, its voxels are `[x, y, z, palette index]` in MagicaVoxel's z-up coordinates.
+#[derive(Clone, Debug)]
+pub struct VoxModel {
+    pub size: [u32; 3],
+    pub voxels: Vec<[u8; 4]>,
+}
+
+/// A model placed in the scene of a `.vox` file, `translation` is the position of the model's centre.
+#[derive(Clone, Copy, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub translation: [i32; 3],
+}
+
+/// A MagicaVoxel file, read from its `SIZE`, `XYZI` and `RGBA` chunks and the scene graph placing the models.
+#[derive(Clone, Debug)]
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    /// The colours indexed by palette index, index 0 is unused. Empty if the file uses the default palette.
+    pub palette: Vec<[u8; 4]>,
+}
+
+/// A node of the scene graph, the transform of a `nTRN` node, the children of a `nGRP` node or the models of a `nSHP` node.
+enum SceneNode {
+    Transform { child: i32, translation: [i32; 3] },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<usize> },
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid_data("unexpected end of the .vox file"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        usize::try_from(self.i32()?).map_err(|_| invalid_data("negative length in the .vox file"))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.len()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| invalid_data("a .vox string isn't valid UTF-8"))
+    }
+
+    fn dict(&mut self) -> io::Result<HashMap<String, String>> {
+        (0..self.len()?).map(|_| Ok((self.string()?, self.string()?))).collect()
+    }
+}
+
+fn write_i32(data: &mut Vec<u8>, value: i32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_dict(data: &mut Vec<u8>, dict: &[(&str, &str)]) {
+    write_i32(data, dict.len() as i32);
+    for string in dict.iter().flat_map(|(key, value)| [key, value]) {
+        write_i32(data, string.len() as i32);
+        data.extend_from_slice(string.as_bytes());
+    }
+}
+
+fn write_chunk(data: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    data.extend_from_slice(id);
+    write_i32(data, content.len() as i32);
+    write_i32(data, 0);
+    data.extend_from_slice(content);
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl VoxFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != VOX_MAGIC {
+            return Err(invalid_data("not a .vox file"));
+        }
+        let _version = reader.i32()?;
+
+        // Every other chunk is a child of the MAIN chunk, so the chunks can be read as one flat list.
+        let mut models = Vec::new();
+        let mut size = None;
+        let mut palette = Vec::new();
+        let mut nodes = HashMap::new();
+        while !reader.data.is_empty() {
+            let id: [u8; 4] = reader.bytes(4)?.try_into().unwrap();
+            let content_len = reader.len()?;
+            let _children_len = reader.len()?;
+            if &id == b"MAIN" {
+                reader.bytes(content_len)?;
+                continue;
+            }
+            let mut content = Reader { data: reader.bytes(content_len)? };
+
+            match &id {
+                b"SIZE" => size = Some([content.len()? as u32, content.len()? as u32, content.len()? as u32]),
+                b"XYZI" => {
+                    let size = size.take().ok_or_else(|| invalid_data("a XYZI chunk without a SIZE chunk"))?;
+                    let voxel_count = content.len()?;
+                    let voxels = content
+                        .bytes(voxel_count * 4)?
+                        .chunks_exact(4)
+                        .map(|voxel| voxel.try_into().unwrap())
+                        .collect();
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // The chunk starts at palette index 1, its last entry is unused.
+                    palette = std::iter::once([0; 4])
+                        .chain(content.bytes(256 * 4)?.chunks_exact(4).take(255).map(|color| color.try_into().unwrap()))
+                        .collect();
+                }
+                b"nTRN" => {
+                    let node = content.i32()?;
+                    content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let frames = content.len()?;
+                    let mut translation = [0; 3];
+                    if frames > 0 {
+                        if let Some(value) = content.dict()?.get("_t") {
+                            for (axis, component) in translation.iter_mut().zip(value.split_whitespace()) {
+                                *axis = component.parse().map_err(|_| invalid_data("an invalid translation in the .vox file"))?;
+                            }
+                        }
+                    }
+                    nodes.insert(node, SceneNode::Transform { child, translation });
+                }
+                b"nGRP" => {
+                    let node = content.i32()?;
+                    content.dict()?;
+                    let children = (0..content.len()?).map(|_| content.i32()).collect::<io::Result<_>>()?;
+                    nodes.insert(node, SceneNode::Group { children });
+                }
+                b"nSHP" => {
+                    let node = content.i32()?;
+                    content.dict()?;
+                    let mut shape_models = Vec::new();
+                    for _ in 0..content.len()? {
+                        shape_models.push(content.len()?);
+                        content.dict()?;
+                    }
+                    nodes.insert(node, SceneNode::Shape { models: shape_models });
+                }
+                _ => {}
+            }
+        }
+
+        // Files without a scene graph place every model at the origin.
+        let mut instances = Vec::new();
+        if nodes.is_empty() {
+            instances.extend((0..models.len()).map(|model| VoxInstance { model, translation: [0; 3] }));
+        } else {
+            Self::collect_instances(&nodes, 0, [0; 3], &mut instances, 0)?;
+        }
+        if let Some(instance) = instances.iter().find(|instance| instance.model >= models.len()) {
+            return Err(invalid_data(&format!("the .vox scene refers to the missing model {}", instance.model)));
+        }
+
+        Ok(Self { models, instances, palette })
+    }
+
+    /// Walks the scene graph from `node`, summing up the translations of the transform nodes. Rotations are ignored.
+    fn collect_instances(
+        nodes: &HashMap<i32, SceneNode>,
+        node: i32,
+        translation: [i32; 3],
+        instances: &mut Vec<VoxInstance>,
+        depth: usize,
+    ) -> io::Result<()> {
+        if depth > nodes.len() {
+            return Err(invalid_data("the .vox scene graph contains a cycle"));
+        }
+        match nodes.get(&node).ok_or_else(|| invalid_data("the .vox scene refers to a missing node"))? {
+            SceneNode::Transform { child, translation: offset } => {
+                let translation = [0, 1, 2].map(|axis| translation[axis] + offset[axis]);
+                Self::collect_instances(nodes, *child, translation, instances, depth + 1)?;
+            }
+            SceneNode::Group { children } => {
+                for child in children {
+                    Self::collect_instances(nodes, *child, translation, instances, depth + 1)?;
+                }
+            }
+            SceneNode::Shape { models } => {
+                instances.extend(models.iter().map(|model| VoxInstance { model: *model, translation }));
+            }
+        }
+        Ok(())
+    }
+
+    /// The voxels of every placed model as world positions relative to `offset`, paired with the
+    /// id `palette_map` maps their palette index to. Voxels whose index isn't mapped are left out.
+    ///
+    /// MagicaVoxel's z-up coordinates are turned into Raxel's y-up ones, with its y axis becoming -z.
+    pub fn voxels<'a>(&'a self, offset: IVec3, palette_map: &'a HashMap<u8, usize>) -> impl Iterator<Item = (IVec3, usize)> + 'a {
+        self.instances.iter().flat_map(move |instance| {
+            let model = &self.models[instance.model];
+            // A model's translation points at its centre, rounded down.
+            let min = [0, 1, 2].map(|axis| instance.translation[axis] - (model.size[axis] / 2) as i32);
+            model.voxels.iter().filter_map(move |[x, y, z, index]| {
+                let id = *palette_map.get(index)?;
+                let pos = [min[0] + *x as i32, min[1] + *y as i32, min[2] + *z as i32];
+                Some((offset + IVec3::new(pos[0], pos[2], -pos[1]), id))
+            })
+        })
+    }
+
+    /// Writes the voxels of every placed model into `world`, see `voxels`.
+    pub fn place(&self, world: &mut World, offset: IVec3, palette_map: &HashMap<u8, usize>) -> io::Result<()> {
+        world.set_voxels(self.voxels(offset, palette_map))
+    }
+
+    /// Exports the voxels of `world` from `min` up to but excluding `max` as models of at most
+    /// `VOX_MODEL_LENGTH`³ voxels, placed so that `place` with `min` as offset restores them.
+    /// Cells that aren't loaded are read without loading them.
+    ///
+    /// Every registered voxel with a model gets a palette index coloured by the average of its face
+    /// textures, the returned map turns these indices back into voxel ids for `place`.
+    pub fn export(world: &World, min: IVec3, max: IVec3) -> io::Result<(Self, HashMap<u8, usize>)> {
+        let mut palette = vec![[0; 4]];
+        let mut palette_indices = HashMap::new();
+        for id in unsafe { VOXEL_REGISTRY.ids() } {
+            let Some(model) = (unsafe { &VOXEL_REGISTRY.get(id).model }) else { continue };
+            if palette.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "a .vox palette can't hold more than 255 voxels"));
+            }
+            let colors: Vec<[u8; 4]> = model.textures().into_iter().filter_map(texture_system::average_color).collect();
+            let color = [0, 1, 2, 3].map(|channel| {
+                (colors.iter().map(|color| color[channel] as u32).sum::<u32>() / colors.len().max(1) as u32) as u8
+            });
+            palette_indices.insert(id, palette.len() as u8);
+            palette.push(color);
+        }
+
+        // World positions relative to `min` map to the .vox scene by turning -z into y and y into z,
+        // the inverse of `voxels`. The scene's y axis then covers `1 - size.z..=0`.
+        let size = (max - min).map(|value| value.max(0));
+        let scene_size = [size.x as u32, size.z as u32, size.y as u32];
+        let scene_min = [0, 1 - size.z, 0];
+        let model_grid = scene_size.map(|length| (length + VOX_MODEL_LENGTH - 1) / VOX_MODEL_LENGTH);
+        let model_index = |grid: [u32; 3]| ((grid[0] * model_grid[1] + grid[1]) * model_grid[2] + grid[2]) as usize;
+
+        let mut models = Vec::new();
+        let mut instances = Vec::new();
+        for grid_x in 0..model_grid[0] {
+            for grid_y in 0..model_grid[1] {
+                for grid_z in 0..model_grid[2] {
+                    let grid = [grid_x, grid_y, grid_z];
+                    let offset = grid.map(|value| value * VOX_MODEL_LENGTH);
+                    let size = [0, 1, 2].map(|axis| (scene_size[axis] - offset[axis]).min(VOX_MODEL_LENGTH));
+                    instances.push(VoxInstance {
+                        model: models.len(),
+                        translation: [0, 1, 2].map(|axis| scene_min[axis] + (offset[axis] + size[axis] / 2) as i32),
+                    });
+                    models.push(VoxModel { size, voxels: Vec::new() });
+                }
+            }
+        }
+
+        let cell_min = min.map(|value| value.div_euclid(CELL_LENGTH as i32));
+        let cell_max = (max - IVec3::one()).map(|value| value.div_euclid(CELL_LENGTH as i32));
+        for cell_x in cell_min.x..=cell_max.x {
+            for cell_y in cell_min.y..=cell_max.y {
+                for cell_z in cell_min.z..=cell_max.z {
+                    let cell_pos = IVec3::new(cell_x, cell_y, cell_z);
+                    let cell = world.cell(cell_pos)?;
+                    let cell_origin = cell_pos * CELL_LENGTH as i32;
+                    let local_min = (min - cell_origin).map(|value| value.max(0));
+                    let local_max = (max - cell_origin).map(|value| value.min(CELL_LENGTH as i32));
+                    for x in local_min.x..local_max.x {
+                        for y in local_min.y..local_max.y {
+                            for z in local_min.z..local_max.z {
+                                let Some(index) = palette_indices.get(&cell.get(x as u8, y as u8, z as u8)) else { continue };
+                                let pos = cell_origin + IVec3::new(x, y, z) - min;
+                                let scene = [pos.x as u32, (-pos.z - scene_min[1]) as u32, pos.y as u32];
+                                let grid = scene.map(|value| value / VOX_MODEL_LENGTH);
+                                let local = scene.map(|value| (value % VOX_MODEL_LENGTH) as u8);
+                                models[model_index(grid)].voxels.push([local[0], local[1], local[2], *index]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let palette_map = palette_indices.into_iter().map(|(id, index)| (index, id)).collect();
+        Ok((Self { models, instances, palette }, palette_map))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes()?)
+    }
+
+    /// Encodes the file with a `SIZE` and `XYZI` chunk per model, a scene graph placing every
+    /// instance under one group and a `RGBA` chunk unless the default palette is used.
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut chunks = Vec::new();
+        for model in &self.models {
+            if model.size.iter().any(|length| *length == 0 || *length > VOX_MODEL_LENGTH) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "a .vox model must be between 1 and 256 voxels long"));
+            }
+            let mut size = Vec::new();
+            model.size.iter().for_each(|length| write_i32(&mut size, *length as i32));
+            write_chunk(&mut chunks, b"SIZE", &size);
+
+            let mut xyzi = Vec::new();
+            write_i32(&mut xyzi, model.voxels.len() as i32);
+            xyzi.extend(model.voxels.iter().flatten());
+            write_chunk(&mut chunks, b"XYZI", &xyzi);
+        }
+
+        let mut root = Vec::new();
+        write_i32(&mut root, 0);
+        write_dict(&mut root, &[]);
+        root.extend([1, -1, -1, 1].iter().flat_map(|value: &i32| value.to_le_bytes()));
+        write_dict(&mut root, &[]);
+        write_chunk(&mut chunks, b"nTRN", &root);
+
+        let mut group = Vec::new();
+        write_i32(&mut group, 1);
+        write_dict(&mut group, &[]);
+        write_i32(&mut group, self.instances.len() as i32);
+        (0..self.instances.len()).for_each(|idx| write_i32(&mut group, 2 + 2 * idx as i32));
+        write_chunk(&mut chunks, b"nGRP", &group);
+
+        for (idx, instance) in self.instances.iter().enumerate() {
+            let node = 2 + 2 * idx as i32;
+            let [x, y, z] = instance.translation;
+            let mut transform = Vec::new();
+            write_i32(&mut transform, node);
+            write_dict(&mut transform, &[]);
+            transform.extend([node + 1, -1, 0, 1].iter().flat_map(|value: &i32| value.to_le_bytes()));
+            write_dict(&mut transform, &[("_t", &format!("{x} {y} {z}"))]);
+            write_chunk(&mut chunks, b"nTRN", &transform);
+
+            let mut shape = Vec::new();
+            write_i32(&mut shape, node + 1);
+            write_dict(&mut shape, &[]);
+            write_i32(&mut shape, 1);
+            write_i32(&mut shape, instance.model as i32);
+            write_dict(&mut shape, &[]);
+            write_chunk(&mut chunks, b"nSHP", &shape);
+        }
+
+        if !self.palette.is_empty() {
+            let mut rgba: Vec<u8> = self.palette.iter().skip(1).take(255).flatten().copied().collect();
+            rgba.resize(256 * 4, 0);
+            write_chunk(&mut chunks, b"RGBA", &rgba);
+        }
+
+        let mut data = Vec::with_capacity(chunks.len() + 20);
+        data.extend_from_slice(&VOX_MAGIC);
+        write_i32(&mut data, VOX_VERSION);
+        data.extend_from_slice(b"MAIN");
+        write_i32(&mut data, 0);
+        write_i32(&mut data, chunks.len() as i32);
+        data.extend(chunks);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        voxel::voxel::test_voxels::{self, AIR, DIRT, GLASS, LEAVES, STONE},
+        world::cell::Cell,
+    };
+
+    use super::*;
+
+    /// The voxel a test world holds at the world position `pos`.
+    fn voxel_at(pos: IVec3) -> usize {
+        [AIR, STONE, DIRT, GLASS, LEAVES][(pos.x * 3 + pos.y * 5 + pos.z * 7).rem_euclid(5) as usize]
+    }
+
+    /// A .vox file of the given chunks, all children of the MAIN chunk.
+    fn vox_bytes(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut children = Vec::new();
+        for (id, content) in chunks {
+            write_chunk(&mut children, id, content);
+        }
+        let mut data = b"VOX ".to_vec();
+        write_i32(&mut data, 150);
+        data.extend_from_slice(b"MAIN");
+        write_i32(&mut data, 0);
+        write_i32(&mut data, children.len() as i32);
+        data.extend(children);
+        data
+    }
+
+    fn i32s(values: &[i32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn a_hand_built_file_is_parsed_into_y_up_voxels() {
+        let xyzi = [i32s(&[3]), vec![1, 2, 3, 1, 0, 0, 0, 2, 1, 0, 0, 9]].concat();
+        let data = vox_bytes(&[
+            (b"SIZE", i32s(&[2, 3, 4])),
+            (b"XYZI", xyzi),
+            // Chunks the parser doesn't know are skipped.
+            (b"MATL", i32s(&[7, 0])),
+        ]);
+        let vox = VoxFile::parse(&data).unwrap();
+        assert_eq!(vox.models.len(), 1);
+        assert_eq!(vox.models[0].size, [2, 3, 4]);
+        assert_eq!(vox.models[0].voxels, vec![[1, 2, 3, 1], [0, 0, 0, 2], [1, 0, 0, 9]]);
+        // Without a scene graph the model is placed at the origin, without a RGBA chunk the default palette is used.
+        assert_eq!(vox.instances.len(), 1);
+        assert_eq!(vox.instances[0].translation, [0; 3]);
+        assert!(vox.palette.is_empty());
+
+        // The model spans -1..1, -1..2 and -2..2 in z-up coordinates, z turns into y and y into -z.
+        // Palette index 9 isn't mapped and is left out.
+        let palette_map = HashMap::from([(1, 10), (2, 20)]);
+        let voxels: Vec<(IVec3, usize)> = vox.voxels(IVec3::new(100, 0, 0), &palette_map).collect();
+        assert_eq!(voxels, vec![(IVec3::new(100, 1, -1), 10), (IVec3::new(99, -2, 1), 20)]);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let xyzi = [i32s(&[1]), vec![0, 0, 0, 1]].concat();
+        assert!(VoxFile::parse(b"RIFF").is_err());
+        // A XYZI chunk needs the SIZE chunk before it.
+        assert!(VoxFile::parse(&vox_bytes(&[(b"XYZI", xyzi.clone())])).is_err());
+
+        let data = vox_bytes(&[(b"SIZE", i32s(&[1, 1, 1])), (b"XYZI", xyzi)]);
+        assert!(VoxFile::parse(&data).is_ok());
+        assert!(VoxFile::parse(&data[..data.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn exported_voxels_survive_a_round_trip() {
+        test_voxels::register();
+        let world = World::new(0, Box::new(|cell: &mut Cell| {
+            let origin = cell.pos * CELL_LENGTH as i32;
+            for x in 0..CELL_LENGTH as u8 {
+                for y in 0..CELL_LENGTH as u8 {
+                    for z in 0..CELL_LENGTH as u8 {
+                        cell.set(x, y, z, voxel_at(origin + IVec3::new(x as i32, y as i32, z as i32)));
+                    }
+                }
+            }
+        }));
+
+        // Longer than a model along x, so the volume is split into two models.
+        let (min, max) = (IVec3::new(-8, -2, -3), IVec3::new(260, 3, 4));
+        let (vox, palette_map) = VoxFile::export(&world, min, max).unwrap();
+        assert_eq!(vox.models.len(), 2);
+        assert!(palette_map.len() > 1);
+
+        let parsed = VoxFile::parse(&vox.to_bytes().unwrap()).unwrap();
+        // The RGBA chunk always holds 256 colours, the unused ones read back as zeroes.
+        assert_eq!(parsed.palette[..vox.palette.len()], vox.palette[..]);
+        let mut voxels: Vec<(IVec3, usize)> = parsed.voxels(min, &palette_map).collect();
+        voxels.sort_by_key(|(pos, _)| (pos.x, pos.y, pos.z));
+
+        let mut expected = Vec::new();
+        for x in min.x..max.x {
+            for y in min.y..max.y {
+                for z in min.z..max.z {
+                    let pos = IVec3::new(x, y, z);
+                    if voxel_at(pos) != AIR {
+                        expected.push((pos, voxel_at(pos)));
+                    }
+                }
+            }
+        }
+        assert_eq!(voxels, expected);
+    }
+}
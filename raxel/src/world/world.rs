@@ -1,41 +1,422 @@
-use ultraviolet::UVec3;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
 
-use crate::render::renderer::Renderer;
+use ultraviolet::{IVec3, Vec3};
 
-use super::cell::Cell;
+use crate::{voxel::voxelface::Norm, VOXEL_REGISTRY};
 
-pub const WORLD_LENGTH: usize = 8;
-pub const WORLD_SIZE: usize = (WORLD_LENGTH * WORLD_LENGTH * WORLD_LENGTH) as usize;
+use super::{cell::{Cell, CELL_LENGTH}, light::LightUpdate, region};
+
+/// The amount of cells that can be loaded at once, which is also the amount of cell slots on the GPU.
+pub const MAX_LOADED_CELLS: usize = 512;
+/// The largest load radius whose cube of cells still fits in `MAX_LOADED_CELLS`.
+pub const MAX_LOAD_RADIUS: u32 = 3;
+/// The amount of cells generated per call to `World::stream`, so that walking doesn't stall a frame.
+const CELLS_LOADED_PER_STREAM: usize = 8;
+
+/// The outcome of a call to `World::stream`.
+#[derive(Debug, Default)]
+pub struct StreamedCells {
+    /// The positions of the cells that left the load radius and were unloaded.
+    pub unloaded: Vec<IVec3>,
+    /// Failures to read cells from their region file, those cells are left unloaded, and to write the
+    /// unloaded modified cells to theirs, those are kept in memory instead.
+    pub errors: Vec<io::Error>,
+}
 
-#[derive(Debug)]
 pub struct World {
-    pub cells: Box<[Cell; WORLD_SIZE]>,
+    pub cells: HashMap<IVec3, Cell>,
+    cell_generator: Box<dyn Fn(&mut Cell)>,
+    load_radius: u32,
+    center: IVec3,
+    /// The directory of region files cells are read from before generating them, set by `save` and `load`.
+    region_dir: Option<PathBuf>,
+    /// The loaded cells whose voxels changed since they were last saved.
+    modified: HashSet<IVec3>,
+    /// Modified cells that were unloaded before the world had a region directory to write them to, or
+    /// whose region file couldn't be written.
+    unsaved: HashMap<IVec3, Cell>,
+    /// Cells whose region file couldn't be read while streaming, they're left unloaded instead of being
+    /// generated over the saved cell. They're tried again once they left the load radius or the world
+    /// was saved.
+    unreadable: HashSet<IVec3>,
 }
 
 impl World {
-    pub fn new(renderer: &mut Renderer, cell_generator: &dyn Fn(&mut Cell)) -> Self {
-        let mut cells: Box<[Cell; WORLD_SIZE]> = unsafe { Box::new_zeroed().assume_init() };
-        for idx in 0..WORLD_SIZE {
-            let x = idx >> 6 & WORLD_LENGTH - 1;
-            let y = idx >> 3 & WORLD_LENGTH - 1;
-            let z = idx >> 0 & WORLD_LENGTH - 1;
+    /// Generates the cells within `load_radius` of the origin. This doesn't touch the GPU, the cells
+    /// are left dirty and get meshed by the renderer once the world is set on an `Instance`.
+    pub fn new(load_radius: u32, cell_generator: Box<dyn Fn(&mut Cell)>) -> Self {
+        Self::with_region_dir(load_radius, cell_generator, None).expect("a world without region files can't fail to load!")
+    }
+
+    /// Loads a world saved with `save` from the region files in `path`, cells that were never saved
+    /// are generated with `cell_generator`.
+    pub fn load(path: impl AsRef<Path>, load_radius: u32, cell_generator: Box<dyn Fn(&mut Cell)>) -> io::Result<Self> {
+        Self::with_region_dir(load_radius, cell_generator, Some(path.as_ref().to_path_buf()))
+    }
+
+    fn with_region_dir(load_radius: u32, cell_generator: Box<dyn Fn(&mut Cell)>, region_dir: Option<PathBuf>) -> io::Result<Self> {
+        assert!(load_radius <= MAX_LOAD_RADIUS, "the load radius can't be larger than {MAX_LOAD_RADIUS}!");
+
+        let mut world = Self {
+            cells: HashMap::new(),
+            cell_generator,
+            load_radius,
+            center: IVec3::zero(),
+            region_dir,
+            modified: HashSet::new(),
+            unsaved: HashMap::new(),
+            unreadable: HashSet::new(),
+        };
+        for pos in world.missing_cells() {
+            world.load_cell(pos)?;
+        }
+        Ok(world)
+    }
 
-            let cell = unsafe { cells.get_unchecked_mut(idx) };
-            cell.pos = UVec3::new(x as u32, y as u32, z as u32);
-            cell_generator(cell);
+    /// Writes the loaded cells and every cell modified since the last save to the region files in `path`,
+    /// which modified cells are written to from then on whenever they unload.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        // Saving somewhere new takes along the cells that were only written to the previous directory.
+        if let Some(region_dir) = self.region_dir.as_ref().filter(|region_dir| *region_dir != path) {
+            fs::create_dir_all(path)?;
+            for entry in fs::read_dir(region_dir)? {
+                let entry = entry?;
+                let is_region = entry.path().extension().is_some_and(|extension| extension == "rxr");
+                if !entry.file_type()?.is_file() || !is_region {
+                    continue;
+                }
+                fs::copy(entry.path(), path.join(entry.file_name()))?;
+            }
         }
 
-        let world = Self {
-            cells,
+        region::write_cells(path, self.cells.values().chain(self.unsaved.values()))?;
+        self.region_dir = Some(path.to_path_buf());
+        self.modified.clear();
+        self.unsaved.clear();
+        self.unreadable.clear();
+        Ok(())
+    }
+
+    pub fn load_radius(&self) -> u32 {
+        self.load_radius
+    }
+
+    pub fn set_load_radius(&mut self, load_radius: u32) {
+        assert!(load_radius <= MAX_LOAD_RADIUS, "the load radius can't be larger than {MAX_LOAD_RADIUS}!");
+        self.load_radius = load_radius;
+    }
+
+    /// Recentres the loaded region on `center`, unloading cells that left the load radius and
+    /// generating some of the cells that entered it. Cells whose region file can't be read are
+    /// reported once and left unloaded.
+    pub fn stream(&mut self, center: Vec3) -> StreamedCells {
+        self.center = Self::cell_pos(center);
+
+        let mut streamed = StreamedCells {
+            unloaded: self.cells.keys().filter(|pos| !self.in_load_radius(**pos)).copied().collect(),
+            errors: Vec::new(),
         };
-        for idx in 0..WORLD_SIZE {
-            renderer.mesh_cell(unsafe { world.cells.get_unchecked(idx) }, &world);
+        if let Err(err) = self.unload_cells(&streamed.unloaded) {
+            streamed.errors.push(err);
+        }
+        let (center, load_radius) = (self.center, self.load_radius as i32);
+        self.unreadable.retain(|pos| (*pos - center).abs().component_max() <= load_radius);
+        for pos in self.missing_cells().into_iter().take(CELLS_LOADED_PER_STREAM) {
+            if let Err(err) = self.load_cell(pos) {
+                streamed.errors.push(io::Error::new(err.kind(), format!("failed to load the cell at {pos:?} from its region file: {err}")));
+                self.unreadable.insert(pos);
+            }
+        }
+        streamed
+    }
+
+    fn in_load_radius(&self, pos: IVec3) -> bool {
+        (pos - self.center).abs().component_max() <= self.load_radius as i32
+    }
+
+    /// Unloads the cells at `positions`, keeping the modified ones in the region files or in memory
+    /// until they're loaded again. Their loaded neighbors are remeshed and the ones below them are relit
+    /// under the open sky. Fails if the modified cells couldn't be written, they're kept in memory then.
+    fn unload_cells(&mut self, positions: &[IVec3]) -> io::Result<()> {
+        let mut modified = Vec::new();
+        for pos in positions {
+            let Some(cell) = self.cells.remove(pos) else { continue };
+            if self.modified.remove(pos) {
+                modified.push(cell);
+            }
+        }
+        // The neighbors were meshed with their border faces hidden by the cells that are gone now.
+        for pos in positions {
+            for offset in Norm::OFFSETS {
+                if let Some(neighbor) = self.cells.get_mut(&(*pos + offset)) {
+                    neighbor.dirty = true;
+                }
+            }
+        }
+        // The cells below are open to the sky again, like the top cells of a freshly loaded column.
+        for pos in positions {
+            let below = *pos - IVec3::unit_y();
+            if self.cells.contains_key(&below) {
+                LightUpdate::new(&mut self.cells).light_cell(below);
+            }
+        }
+
+        let result = match &self.region_dir {
+            Some(region_dir) => region::write_cells(region_dir, modified.iter()),
+            None => Ok(()),
+        };
+        // The cells stay in memory, so the next `save` or load of them still sees the changes.
+        if self.region_dir.is_none() || result.is_err() {
+            self.unsaved.extend(modified.into_iter().map(|cell| (cell.pos, cell)));
+        }
+        result
+    }
+
+    /// The positions of the cells within the load radius that aren't loaded yet, nearest first.
+    fn missing_cells(&self) -> Vec<IVec3> {
+        let load_radius = self.load_radius as i32;
+        let mut missing = Vec::new();
+        for x in -load_radius..=load_radius {
+            for y in -load_radius..=load_radius {
+                for z in -load_radius..=load_radius {
+                    let pos = self.center + IVec3::new(x, y, z);
+                    if !self.cells.contains_key(&pos) && !self.unreadable.contains(&pos) {
+                        missing.push(pos);
+                    }
+                }
+            }
+        }
+        missing.sort_by_key(|pos| (*pos - self.center).mag_sq());
+        missing
+    }
+
+    /// Loads the cell at `pos`, preferring unsaved changes and the region files over generating it.
+    fn load_cell(&mut self, pos: IVec3) -> io::Result<()> {
+        let mut cell = match self.unsaved.remove(&pos) {
+            Some(cell) => {
+                self.modified.insert(pos);
+                cell
+            }
+            None => self.read_cell(pos)?,
+        };
+        cell.dirty = true;
+        self.cells.insert(pos, cell);
+        LightUpdate::new(&mut self.cells).light_cell(pos);
+
+        // The neighbors were meshed with faces against the empty space this cell now fills.
+        for offset in Norm::OFFSETS {
+            if let Some(neighbor) = self.cells.get_mut(&(pos + offset)) {
+                neighbor.dirty = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the cell at `pos` from the region files, or generates it if it was never saved.
+    fn read_cell(&self, pos: IVec3) -> io::Result<Cell> {
+        if let Some(cell) = self.region_dir.as_ref().map(|region_dir| region::read_cell(region_dir, pos)).transpose()?.flatten() {
+            return Ok(cell);
+        }
+        let mut cell = Cell::new(pos);
+        (self.cell_generator)(&mut cell);
+        Ok(cell)
+    }
+
+    /// The cell at `pos` as it is or would be when loaded, cells that aren't loaded are read without loading them.
+    pub fn cell(&self, pos: IVec3) -> io::Result<Cow<Cell>> {
+        match self.cells.get(&pos).or_else(|| self.unsaved.get(&pos)) {
+            Some(cell) => Ok(Cow::Borrowed(cell)),
+            None => Ok(Cow::Owned(self.read_cell(pos)?)),
         }
-        world
     }
 
-    #[inline(always)]
-    pub const fn encode(x: u32, y: u32, z: u32) -> usize {
-        ((x as usize) << 6) | ((y as usize) << 3) | ((z as usize) << 0)
+    /// Sets many voxels at once, unlike `set_voxel` this also reaches cells that aren't loaded.
+    /// Those are loaded for the edit and kept as modified cells once they're unloaded again. Fails if a
+    /// cell can't be read, or if the edited cells can't be written once they're unloaded, in which
+    /// case the edit is kept in memory.
+    pub fn set_voxels(&mut self, voxels: impl IntoIterator<Item = (IVec3, usize)>) -> io::Result<()> {
+        let mut loaded = Vec::new();
+        for (pos, id) in voxels {
+            let cell_pos = Self::locate(pos).0;
+            if !self.cells.contains_key(&cell_pos) {
+                self.load_cell(cell_pos)?;
+                loaded.push(cell_pos);
+            }
+            self.set_voxel(pos, id);
+        }
+
+        // The renderer never sees the loaded cells outside of the load radius, so they're unloaded right away.
+        loaded.retain(|pos| !self.in_load_radius(*pos));
+        self.unload_cells(&loaded)
+    }
+
+    /// The loaded cells adjacent to the cell at `pos`, in the order of `Norm::VALUES`.
+    pub fn neighbor_cells(&self, pos: IVec3) -> [Option<&Cell>; 6] {
+        Norm::OFFSETS.map(|offset| self.cells.get(&(pos + offset)))
+    }
+
+    /// The position of the cell containing the world position `pos`.
+    pub fn cell_pos(pos: Vec3) -> IVec3 {
+        IVec3::new(
+            (pos.x / CELL_LENGTH as f32).floor() as i32,
+            (pos.y / CELL_LENGTH as f32).floor() as i32,
+            (pos.z / CELL_LENGTH as f32).floor() as i32,
+        )
+    }
+
+    /// The position of the voxel containing the world position `pos`.
+    pub fn voxel_pos(pos: Vec3) -> IVec3 {
+        IVec3::new(pos.x.floor() as i32, pos.y.floor() as i32, pos.z.floor() as i32)
+    }
+
+    /// Splits a world voxel position into the position of the owning cell and the local position inside it.
+    fn locate(pos: IVec3) -> (IVec3, [u8; 3]) {
+        let cell_pos = pos.map(|value| value.div_euclid(CELL_LENGTH as i32));
+        let local = pos - cell_pos * CELL_LENGTH as i32;
+        (cell_pos, [local.x as u8, local.y as u8, local.z as u8])
+    }
+
+    /// The voxel at `pos`, or `None` if the cell containing it isn't loaded.
+    pub fn get_voxel(&self, pos: IVec3) -> Option<usize> {
+        let (cell_pos, [x, y, z]) = Self::locate(pos);
+        Some(self.cells.get(&cell_pos)?.get(x, y, z))
+    }
+
+    /// Sets the voxel at `pos` and marks every cell whose mesh is affected by the change as dirty.
+    /// Positions in cells that aren't loaded are ignored.
+    pub fn set_voxel(&mut self, pos: IVec3, id: usize) {
+        let (cell_pos, [x, y, z]) = Self::locate(pos);
+        let Some(cell) = self.cells.get_mut(&cell_pos) else { return };
+
+        let old_id = cell.get(x, y, z);
+        if old_id == id {
+            return;
+        }
+        cell.set(x, y, z, id);
+        cell.dirty = true;
+        self.modified.insert(cell_pos);
+        LightUpdate::new(&mut self.cells).update_voxel(pos);
+
+        // Swapping one opaque voxel for another hides the same faces of the neighbors.
+        if unsafe { VOXEL_REGISTRY.get(old_id).is_opaque() && VOXEL_REGISTRY.get(id).is_opaque() } {
+            return;
+        }
+
+        // A voxel on the border of a cell can hide or reveal faces of the neighbouring cell.
+        const LAST: u8 = CELL_LENGTH as u8 - 1;
+        for (local, axis) in [(x, IVec3::unit_x()), (y, IVec3::unit_y()), (z, IVec3::unit_z())] {
+            let neighbor = match local {
+                0 => pos - axis,
+                LAST => pos + axis,
+                _ => continue,
+            };
+            if let Some(neighbor_cell) = self.cells.get_mut(&Self::locate(neighbor).0) {
+                neighbor_cell.dirty = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        voxel::voxel::test_voxels::{self, STONE},
+        world::light::{LightChannel, MAX_LIGHT},
+    };
+
+    use super::*;
+
+    const LAST: u8 = CELL_LENGTH as u8 - 1;
+
+    /// The sky light of the top layer of the cell at the origin.
+    fn top_sky_light(world: &World) -> Vec<u8> {
+        let cell = &world.cells[&IVec3::zero()];
+        (0..CELL_LENGTH as u8)
+            .flat_map(|x| (0..CELL_LENGTH as u8).map(move |z| Cell::encode(x, LAST, z)))
+            .map(|idx| cell.light.get(idx, LightChannel::SKY))
+            .collect()
+    }
+
+    #[test]
+    fn cells_below_unloaded_cells_are_relit_under_the_open_sky() {
+        test_voxels::register();
+        // A stone ceiling one cell above the origin, air everywhere else.
+        let mut world = World::new(1, Box::new(|cell| {
+            if cell.pos.y == 1 {
+                for idx in 0..CELL_LENGTH * CELL_LENGTH * CELL_LENGTH {
+                    cell.voxels.set(idx, STONE);
+                }
+            }
+        }));
+        assert!(top_sky_light(&world).iter().all(|level| *level == 0));
+
+        for cell in world.cells.values_mut() {
+            cell.dirty = false;
+        }
+        world.stream(Vec3::new(16.0, -16.0, 16.0));
+        assert!(!world.cells.contains_key(&IVec3::unit_y()));
+        assert!(world.cells[&IVec3::zero()].dirty);
+        assert!(top_sky_light(&world).iter().all(|level| *level == MAX_LIGHT));
+
+        // Loading the ceiling again darkens the cell as before.
+        while world.cells.len() < 27 {
+            world.stream(Vec3::new(16.0, 16.0, 16.0));
+        }
+        assert!(top_sky_light(&world).iter().all(|level| *level == 0));
+    }
+
+    #[test]
+    fn unreadable_cells_are_retried_after_leaving_the_load_radius_or_saving() {
+        test_voxels::register();
+        let dir = std::env::temp_dir().join(format!("raxel-world-unreadable-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut world = World::load(&dir, 1, Box::new(|_| {})).unwrap();
+
+        let far = IVec3::new(100, 0, 0);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(region::region_path(&dir, region::region_pos(far)), b"not a region").unwrap();
+        let far_center = Vec3::new(100.5, 0.5, 0.5) * CELL_LENGTH as f32;
+        let mut errors = 0;
+        for _ in 0..4 {
+            errors += world.stream(far_center).errors.len();
+        }
+        assert!(errors > 0);
+        assert!(world.unreadable.contains(&far));
+        assert!(!world.cells.contains_key(&far));
+
+        world.stream(Vec3::zero());
+        assert!(world.unreadable.is_empty());
+
+        // Saving after the region file was replaced reads the cell again as well.
+        world.stream(far_center);
+        assert!(world.unreadable.contains(&far));
+        fs::remove_file(region::region_path(&dir, region::region_pos(far))).unwrap();
+        world.save(&dir).unwrap();
+        assert!(world.unreadable.is_empty());
+        assert!(world.stream(far_center).errors.is_empty());
+        assert!(world.cells.contains_key(&far));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn neighbors_of_unloaded_cells_are_remeshed() {
+        test_voxels::register();
+        let mut world = World::new(1, Box::new(|_| {}));
+        for cell in world.cells.values_mut() {
+            cell.dirty = false;
+        }
+
+        // Moving one cell along x unloads the cells at x = -1 and loads the ones at x = 2.
+        world.stream(Vec3::new(48.0, 16.0, 16.0));
+        assert!(!world.cells.contains_key(&-IVec3::unit_x()));
+        assert!(world.cells[&IVec3::zero()].dirty);
+        assert!(world.cells[&-IVec3::unit_y()].dirty);
     }
 }